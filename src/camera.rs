@@ -1,6 +1,31 @@
+use std::str::FromStr;
+
 use glam::{Mat3, Mat4, Quat, Vec3, Vec4};
 
-use crate::utils::arb_rotate;
+use voxel_garden::utils::arb_rotate;
+
+/// Which kind of camera movement to use, without any state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    Trackball,
+    Flying,
+    OnGround,
+}
+
+impl FromStr for CameraMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trackball" => Ok(CameraMode::Trackball),
+            "flying" => Ok(CameraMode::Flying),
+            "on-ground" => Ok(CameraMode::OnGround),
+            _ => Err(format!(
+                "invalid camera mode {s:?}, expected trackball, flying or on-ground"
+            )),
+        }
+    }
+}
 
 pub enum Movement {
     Trackball {
@@ -21,6 +46,28 @@ pub enum Movement {
 }
 
 impl Movement {
+    /// Creates a camera of the given kind at `position`, looking along the z-axis.
+    /// The trackball camera ignores the position.
+    pub fn new(mode: CameraMode, position: Vec3) -> Self {
+        match mode {
+            CameraMode::Trackball => Movement::Trackball {
+                down_pos: (0.0, 0.0),
+                matrix: Mat4::IDENTITY,
+            },
+            CameraMode::Flying => Movement::Flying {
+                position,
+                look_h: 0.0,
+                look_v: 0.0,
+            },
+            CameraMode::OnGround => Movement::OnGround {
+                position,
+                velocity: Vec3::ZERO,
+                look_h: 0.0,
+                look_v: 0.0,
+            },
+        }
+    }
+
     pub fn camera_matrix(&self) -> Mat4 {
         match self {
             Movement::Trackball {
@@ -0,0 +1,513 @@
+//! The chunks that have been generated so far, and edits made to them.
+
+use std::collections::{HashMap, HashSet};
+
+use glam::{IVec2, IVec3};
+
+use crate::mesh::{greedy_mesh, lod_mesh, ChunkMesh};
+use crate::terrain::{model_voxels, proxy_voxels, GenerationPositions, Object};
+use crate::voxels::{ChunkVoxels, Material};
+use crate::{Model, CHUNK_SIZE};
+
+/// Ids of planted objects start here, well above the ids of generated objects, which are
+/// numbered by column.
+const FIRST_PLANTED_ID: u64 = 1 << 32;
+
+/// An object in a chunk, by the chunk owning it and the object's [`Object::id`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectId {
+    pub chunk: IVec2,
+    pub id: u64,
+}
+
+/// A copy of what one of a chunk's meshes is built from, so it can be built without holding on
+/// to the chunks. Made by [`Chunks::mesh_source`].
+pub struct MeshSource {
+    pub chunk: IVec2,
+    pub lod: usize,
+    pub proxies: bool,
+    revision: u64,
+    origin: IVec2,
+    voxels: ChunkVoxels,
+    /// The large objects in the chunk and around it, if the mesh has proxies.
+    objects: Vec<Object>,
+}
+
+impl MeshSource {
+    pub fn build(&self) -> ChunkMesh {
+        if self.proxies {
+            let voxels = proxy_voxels(&self.voxels, self.origin, &self.objects);
+            greedy_mesh(&voxels, self.origin)
+        } else {
+            lod_mesh(&self.voxels, self.origin, self.lod)
+        }
+    }
+}
+
+/// Generated chunks, indexed by chunk coordinate.
+///
+/// Objects near the edge of a chunk can reach into its neighbours. Those voxels are written into
+/// the neighbour when it is loaded, or kept until it is inserted.
+///
+/// Voxels can be edited with [`Chunks::set_voxel`] and friends. Edits are kept separately from
+/// the generated chunks and applied again whenever a chunk is inserted, so they survive the
+/// chunk being regenerated. Edits to chunks that haven't been generated yet are applied when
+/// they are.
+///
+/// Objects can also be planted with [`Chunks::add_object`] and removed with
+/// [`Chunks::remove_object`]. Both are kept the same way as edits.
+///
+/// Edited chunks are marked dirty and their instance data is only updated by
+/// [`Chunks::remesh_dirty`], so a brush stroke editing many voxels only re-meshes every chunk
+/// once.
+#[derive(Default)]
+pub struct Chunks {
+    chunks: HashMap<IVec2, GenerationPositions>,
+    /// Voxels that objects in other chunks spill into each chunk, in world coordinates.
+    spilled: HashMap<IVec2, Vec<(IVec3, Material)>>,
+    /// Every edited voxel per chunk, in world coordinates. `None` is air.
+    edits: HashMap<IVec2, HashMap<IVec3, Option<Material>>>,
+    /// Planted objects per owning chunk.
+    planted: HashMap<IVec2, Vec<Object>>,
+    /// Ids of generated objects that have been removed, per owning chunk.
+    removed: HashMap<IVec2, HashSet<u64>>,
+    /// How many objects have been planted, for giving them ids.
+    planted_count: u64,
+    /// Which object every object voxel belongs to, per owning chunk, in world coordinates.
+    /// Where objects overlap, the voxel belongs to the first one.
+    object_voxels: HashMap<IVec2, HashMap<IVec3, u64>>,
+    dirty: HashSet<IVec2>,
+}
+
+impl Chunks {
+    pub fn new() -> Self {
+        Chunks::default()
+    }
+
+    pub fn get(&self, chunk: &IVec2) -> Option<&GenerationPositions> {
+        self.chunks.get(chunk)
+    }
+
+    pub fn contains_key(&self, chunk: &IVec2) -> bool {
+        self.chunks.contains_key(chunk)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Adds a generated chunk, or replaces a regenerated one. Objects that have been removed
+    /// are taken out of it, voxels spilled into it by its neighbours, planted objects and edits
+    /// are applied on top, and its own overflowing voxels are handed over to its neighbours.
+    pub fn insert(&mut self, chunk: IVec2, mut data: GenerationPositions) {
+        let mut changed = self.remove_generated(&mut data);
+        for &(position, material) in self.spilled.get(&chunk).into_iter().flatten() {
+            set_local(&mut data, position, Some(material));
+            changed = true;
+        }
+        if let Some(planted) = self.planted.get(&chunk) {
+            data.objects.extend(planted.iter().cloned());
+        }
+        // Planted objects in the neighbours can reach into this chunk too
+        let planted_around = around(chunk).filter_map(|c| self.planted.get(&c)).flatten();
+        for (position, material) in planted_around.flat_map(object_voxels) {
+            set_local(&mut data, position, Some(material));
+            changed = true;
+        }
+        for (&position, &material) in self.edits.get(&chunk).into_iter().flatten() {
+            set_local(&mut data, position, material);
+            changed = true;
+        }
+        if changed {
+            data.remesh();
+        }
+
+        // A regenerated chunk already handed over its objects the first time
+        let overflow = std::mem::take(&mut data.overflow);
+        let regenerated = self.chunks.insert(chunk, data).is_some();
+        self.dirty.remove(&chunk);
+        self.index_objects(chunk);
+        if regenerated {
+            return;
+        }
+        for (position, material) in overflow {
+            let neighbour = chunk_of(position);
+            self.spilled
+                .entry(neighbour)
+                .or_default()
+                .push((position, material));
+            // Edits win over objects from the neighbours
+            let edited = self.is_edited(position);
+            if let Some(neighbour_data) = self.chunks.get_mut(&neighbour) {
+                if !edited {
+                    set_local(neighbour_data, position, Some(material));
+                    self.dirty.insert(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Takes the generated objects that have been removed out of a newly generated chunk, and
+    /// fills in the ground where they were. Returns whether anything was removed.
+    fn remove_generated(&self, data: &mut GenerationPositions) -> bool {
+        let chunk = chunk_of(IVec3::new(data.origin.x, 0, data.origin.y));
+        let Some(removed_ids) = self.removed.get(&chunk).filter(|ids| !ids.is_empty()) else {
+            return false;
+        };
+        let (removed, kept): (Vec<Object>, Vec<Object>) = std::mem::take(&mut data.objects)
+            .into_iter()
+            .partition(|object| removed_ids.contains(&object.id));
+        data.objects = kept;
+        let removed: HashSet<IVec3> = removed
+            .iter()
+            .flat_map(object_voxels)
+            .map(|(position, _)| position)
+            .collect();
+        if removed.is_empty() {
+            return false;
+        }
+        // Overlapping voxels of the other objects in the chunk are painted again below
+        data.overflow
+            .retain(|(position, _)| !removed.contains(position));
+
+        let origin = IVec3::new(data.origin.x, 0, data.origin.y);
+        for &position in &removed {
+            set_local(data, position, None);
+        }
+        let ground: Vec<(IVec3, Material)> = data
+            .ground
+            .iter()
+            .map(|&(position, material)| (position + origin, material))
+            .filter(|(position, _)| removed.contains(position))
+            .collect();
+        let others: Vec<(IVec3, Material)> = data
+            .objects
+            .iter()
+            .flat_map(object_voxels)
+            .filter(|(position, _)| removed.contains(position))
+            .collect();
+        for (position, material) in ground.into_iter().chain(others) {
+            set_local(data, position, Some(material));
+        }
+        true
+    }
+
+    /// The voxel at world coordinate `position`. Air and voxels in chunks that haven't been
+    /// generated yet are `None`.
+    pub fn get_voxel(&self, position: IVec3) -> Option<Material> {
+        let data = self.chunks.get(&chunk_of(position))?;
+        data.voxels
+            .get(position - IVec3::new(data.origin.x, 0, data.origin.y))
+    }
+
+    /// Height of the water surface in the column at world coordinates `x`, `z`: the top of its
+    /// highest water voxel. `None` if the column has no water or hasn't been generated.
+    pub fn water_surface(&self, x: i32, z: i32) -> Option<f32> {
+        let data = self.chunks.get(&chunk_of(IVec3::new(x, 0, z)))?;
+        let local = |y| IVec3::new(x - data.origin.x, y, z - data.origin.y);
+        let (min_y, max_y) = data.voxels.y_range();
+        (min_y..max_y)
+            .rev()
+            .find(|&y| data.voxels.get(local(y)).is_some_and(|m| m.is_water))
+            .map(|y| y as f32 + 0.5)
+    }
+
+    pub fn set_voxel(&mut self, position: IVec3, material: Material) {
+        self.edit(position, Some(material));
+    }
+
+    pub fn remove_voxel(&mut self, position: IVec3) {
+        self.edit(position, None);
+    }
+
+    /// Sets every voxel in the box between `min` and `max` (inclusive), or removes them if
+    /// `material` is `None`.
+    pub fn fill_region(&mut self, min: IVec3, max: IVec3, material: Option<Material>) {
+        for position in region(min, max) {
+            self.edit(position, material);
+        }
+    }
+
+    /// Replaces every `from` voxel in the box between `min` and `max` (inclusive) with `to`, or
+    /// removes them if `to` is `None`. Only chunks that have been generated are looked at.
+    pub fn replace_region(&mut self, min: IVec3, max: IVec3, from: Material, to: Option<Material>) {
+        for position in region(min, max) {
+            if self.get_voxel(position) == Some(from) {
+                self.edit(position, to);
+            }
+        }
+    }
+
+    /// Whether the voxel at world coordinate `position` has been edited.
+    pub fn is_edited(&self, position: IVec3) -> bool {
+        self.edits
+            .get(&chunk_of(position))
+            .is_some_and(|edits| edits.contains_key(&position))
+    }
+
+    /// Sets a voxel back to `material`, which is kept as an edit if `edited` and taken as the
+    /// voxel the world generated there otherwise. For undoing edits.
+    pub fn restore_voxel(&mut self, position: IVec3, material: Option<Material>, edited: bool) {
+        if edited {
+            self.edit(position, material);
+        } else {
+            if let Some(edits) = self.edits.get_mut(&chunk_of(position)) {
+                edits.remove(&position);
+            }
+            self.paint(position, material);
+        }
+    }
+
+    fn edit(&mut self, position: IVec3, material: Option<Material>) {
+        self.edits
+            .entry(chunk_of(position))
+            .or_default()
+            .insert(position, material);
+        self.paint(position, material);
+    }
+
+    /// Changes a voxel in a loaded chunk without recording it as an edit.
+    fn paint(&mut self, position: IVec3, material: Option<Material>) {
+        let chunk = chunk_of(position);
+        if let Some(data) = self.chunks.get_mut(&chunk) {
+            set_local(data, position, material);
+            self.dirty.insert(chunk);
+        }
+    }
+
+    /// Makes an object to plant out of `models`, with an id no other object has.
+    pub fn new_object(&mut self, models: Vec<Model>) -> Object {
+        let id = FIRST_PLANTED_ID + self.planted_count;
+        self.planted_count += 1;
+        Object { id, models }
+    }
+
+    /// Adds an object as one of the objects of `chunk`, which should be the chunk containing
+    /// its base. It replaces any edits where it is placed, and returns the edits it replaced.
+    /// New objects are made with [`Chunks::new_object`].
+    pub fn add_object(&mut self, chunk: IVec2, object: Object) -> Vec<(IVec3, Option<Material>)> {
+        let mut replaced = Vec::new();
+        for (position, material) in object_voxels(&object) {
+            if let Some(edits) = self.edits.get_mut(&chunk_of(position)) {
+                if let Some(edit) = edits.remove(&position) {
+                    replaced.push((position, edit));
+                }
+            }
+            self.paint(position, Some(material));
+        }
+        self.insert_object(chunk, object);
+        replaced
+    }
+
+    /// Puts back an object taken out with [`Chunks::remove_object`]. Unlike
+    /// [`Chunks::add_object`], edits made where it was are kept.
+    pub fn restore_object(&mut self, chunk: IVec2, object: Object) {
+        for (position, material) in object_voxels(&object) {
+            if !self.is_edited(position) {
+                self.paint(position, Some(material));
+            }
+        }
+        self.insert_object(chunk, object);
+    }
+
+    /// Makes `object` one of the objects of `chunk`. Its voxels need to be painted already.
+    fn insert_object(&mut self, chunk: IVec2, object: Object) {
+        if let Some(data) = self.chunks.get_mut(&chunk) {
+            data.objects.push(object.clone());
+        }
+        if object.id >= FIRST_PLANTED_ID {
+            self.planted.entry(chunk).or_default().push(object);
+        } else {
+            // A generated object is part of the chunk again when it is generated, but its
+            // voxels in the neighbours were taken out of what it spilled
+            if let Some(removed) = self.removed.get_mut(&chunk) {
+                removed.remove(&object.id);
+            }
+            for (position, material) in object_voxels(&object) {
+                if chunk_of(position) != chunk {
+                    self.spilled
+                        .entry(chunk_of(position))
+                        .or_default()
+                        .push((position, material));
+                }
+            }
+        }
+        self.index_objects(chunk);
+    }
+
+    /// The object owning the voxel at world coordinate `position`. Objects can reach into
+    /// neighbouring chunks, so those are searched too. Edited voxels don't belong to any
+    /// object.
+    pub fn object_at(&self, position: IVec3) -> Option<ObjectId> {
+        if self.is_edited(position) {
+            return None;
+        }
+        around(chunk_of(position)).find_map(|chunk| {
+            let id = *self.object_voxels.get(&chunk)?.get(&position)?;
+            Some(ObjectId { chunk, id })
+        })
+    }
+
+    /// Rebuilds the lookup from voxels to objects for the objects of `chunk`. Needs to be
+    /// called whenever they change.
+    fn index_objects(&mut self, chunk: IVec2) {
+        let Some(data) = self.chunks.get(&chunk) else {
+            return;
+        };
+        let mut index = HashMap::new();
+        for object in &data.objects {
+            for (position, _) in object_voxels(object) {
+                index.entry(position).or_insert(object.id);
+            }
+        }
+        self.object_voxels.insert(chunk, index);
+    }
+
+    /// Removes an object, generated or planted, and returns it. The ground and other objects
+    /// it was covering are filled in again, but edited voxels are left alone. Removed generated
+    /// objects stay removed when the chunk is generated again.
+    pub fn remove_object(&mut self, id: ObjectId) -> Option<Object> {
+        let data = self.chunks.get_mut(&id.chunk)?;
+        let index = data.objects.iter().position(|o| o.id == id.id)?;
+        let object = data.objects.remove(index);
+        if object.id >= FIRST_PLANTED_ID {
+            if let Some(planted) = self.planted.get_mut(&id.chunk) {
+                planted.retain(|p| p.id != object.id);
+            }
+        } else {
+            self.removed.entry(id.chunk).or_default().insert(object.id);
+        }
+        self.index_objects(id.chunk);
+
+        let removed: HashSet<IVec3> = object_voxels(&object)
+            .map(|(position, _)| position)
+            .filter(|&position| !self.is_edited(position))
+            .collect();
+        for spilled in self.spilled.values_mut() {
+            spilled.retain(|(position, _)| !removed.contains(position));
+        }
+
+        // Clear the object, then fill in the ground and the other objects that overlapped it
+        let mut fill: Vec<(IVec3, Option<Material>)> = removed.iter().map(|&p| (p, None)).collect();
+        let affected: HashSet<IVec2> = removed.iter().map(|&p| chunk_of(p)).collect();
+        for chunk in &affected {
+            let Some(data) = self.chunks.get(chunk) else {
+                continue;
+            };
+            let origin = IVec3::new(data.origin.x, 0, data.origin.y);
+            fill.extend(
+                data.ground
+                    .iter()
+                    .map(|&(position, material)| (position + origin, Some(material)))
+                    .filter(|(position, _)| removed.contains(position)),
+            );
+        }
+        let nearby: HashSet<IVec2> = affected.iter().flat_map(|&c| around(c)).collect();
+        for chunk in &nearby {
+            let Some(data) = self.chunks.get(chunk) else {
+                continue;
+            };
+            fill.extend(
+                data.objects
+                    .iter()
+                    .flat_map(object_voxels)
+                    .filter(|(position, _)| removed.contains(position))
+                    .map(|(position, material)| (position, Some(material))),
+            );
+        }
+        for (position, material) in fill {
+            self.paint(position, material);
+        }
+        Some(object)
+    }
+
+    /// What the mesh of `chunk` at level of detail `lod` is built from. With `proxies`, it's
+    /// the full-detail mesh with large objects replaced by proxies, including the parts of its
+    /// neighbours' objects that reach into it. `None` if the chunk hasn't been generated or the
+    /// mesh is built already.
+    pub fn mesh_source(&self, chunk: IVec2, lod: usize, proxies: bool) -> Option<MeshSource> {
+        let data = self.chunks.get(&chunk)?;
+        if data.built_mesh(lod, proxies).is_some() {
+            return None;
+        }
+        let objects = if proxies {
+            around(chunk)
+                .filter_map(|c| self.chunks.get(&c))
+                .flat_map(|data| &data.objects)
+                .filter(|object| object.has_proxy())
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Some(MeshSource {
+            chunk,
+            lod,
+            proxies,
+            revision: data.revision,
+            origin: data.origin,
+            voxels: data.voxels.clone(),
+            objects,
+        })
+    }
+
+    /// Keeps a mesh built from `source`, unless the chunk has changed since.
+    pub fn insert_mesh(&mut self, source: &MeshSource, mesh: ChunkMesh) {
+        if let Some(data) = self.chunks.get(&source.chunk) {
+            if data.revision == source.revision {
+                data.set_mesh(source.lod, source.proxies, mesh);
+            }
+        }
+    }
+
+    pub fn is_dirty(&self, chunk: IVec2) -> bool {
+        self.dirty.contains(&chunk)
+    }
+
+    /// Updates the instance data of every chunk edited since the last call, and returns which
+    /// chunks were updated.
+    pub fn remesh_dirty(&mut self) -> Vec<IVec2> {
+        let dirty: Vec<IVec2> = self.dirty.drain().collect();
+        for chunk in &dirty {
+            if let Some(data) = self.chunks.get_mut(chunk) {
+                data.remesh();
+            }
+        }
+        dirty
+    }
+}
+
+fn set_local(data: &mut GenerationPositions, position: IVec3, material: Option<Material>) {
+    let origin = IVec3::new(data.origin.x, 0, data.origin.y);
+    data.voxels.set(position - origin, material);
+}
+
+fn object_voxels(object: &Object) -> impl Iterator<Item = (IVec3, Material)> + '_ {
+    object.models.iter().flat_map(model_voxels)
+}
+
+/// The chunk and its eight neighbours.
+fn around(chunk: IVec2) -> impl Iterator<Item = IVec2> {
+    (-1..=1).flat_map(move |dz| (-1..=1).map(move |dx| chunk + IVec2::new(dx, dz)))
+}
+
+/// Every position in the box between `min` and `max` (inclusive).
+fn region(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3> {
+    let (min, max) = (min.min(max), min.max(max));
+    (min.y..=max.y).flat_map(move |y| {
+        (min.z..=max.z).flat_map(move |z| (min.x..=max.x).map(move |x| IVec3::new(x, y, z)))
+    })
+}
+
+/// The chunk containing the voxel at world coordinate `position`. Chunks are `CHUNK_SIZE`
+/// voxels wide, so for example x = -1 is in chunk -1 and x = -32 in chunk -1 too.
+pub fn chunk_of(position: IVec3) -> IVec2 {
+    IVec2::new(
+        position.x.div_euclid(CHUNK_SIZE),
+        position.z.div_euclid(CHUNK_SIZE),
+    )
+}
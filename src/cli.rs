@@ -0,0 +1,519 @@
+//! Command line and config file options.
+//!
+//! Every option can be given on the command line as `--name value` or in the config file as
+//! `name = value`, one per line. Options on the command line override the config file.
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use glam::{IVec2, Vec3};
+use noise::Perlin;
+
+use voxel_garden::biomes::BiomeConfig;
+use voxel_garden::map::MapExport;
+use voxel_garden::models::heightmap::{Heightmap, HeightmapWrap};
+use voxel_garden::terrain::{TerrainConfig, WorldBounds};
+use voxel_garden::{World, CHUNK_SIZE};
+
+use crate::camera::CameraMode;
+use crate::rendering::RenderPath;
+use crate::settings::{Settings, SettingsFile, DEFAULT_PRESET};
+
+struct OptionHelp {
+    name: &'static str,
+    value: &'static str,
+    help: &'static str,
+}
+
+const OPTIONS: &[OptionHelp] = &[
+    OptionHelp {
+        name: "config",
+        value: "<path>",
+        help: "Read options from a file with one `name = value` per line. \
+               Options on the command line take precedence.",
+    },
+    OptionHelp {
+        name: "seed",
+        value: "<integer>",
+        help: "Seed for the terrain noise. [default: 555]",
+    },
+    OptionHelp {
+        name: "biome-seed",
+        value: "<integer>",
+        help: "Seed for the biome and plant noise. [default: seed + 111]",
+    },
+    OptionHelp {
+        name: "settings",
+        value: "<path>",
+        help: "File that the Debug window settings are saved to and loaded from. \
+               [default: settings.cfg]",
+    },
+    OptionHelp {
+        name: "preset",
+        value: "<name>",
+        help: "Which preset in the settings file to load at startup. [default: default]",
+    },
+    OptionHelp {
+        name: "render-distance",
+        value: "<1-64>",
+        help: "How many chunks to draw in each direction from the camera. \
+               Overrides the settings preset.",
+    },
+    OptionHelp {
+        name: "render-path",
+        value: "<greedy|instanced>",
+        help: "Draw chunks as greedy meshes or as one instance per voxel. \
+               Overrides the settings preset.",
+    },
+    OptionHelp {
+        name: "window-size",
+        value: "<width>,<height>",
+        help: "Initial window size in pixels. [default: 800,800]",
+    },
+    OptionHelp {
+        name: "camera",
+        value: "<on-ground|flying|trackball>",
+        help: "Starting camera mode. [default: on-ground]",
+    },
+    OptionHelp {
+        name: "position",
+        value: "<x>,<y>,<z>",
+        help: "Starting camera position. [default: 0,0,0]",
+    },
+    OptionHelp {
+        name: "sample-rate",
+        value: "<number>",
+        help: "How quickly the terrain height changes. Smaller is smoother. [default: 0.004]",
+    },
+    OptionHelp {
+        name: "max-height",
+        value: "<number>",
+        help: "Height of the highest possible terrain. [default: 40]",
+    },
+    OptionHelp {
+        name: "water-level",
+        value: "<number>",
+        help: "Terrain at or below this height is covered by water. [default: 6]",
+    },
+    OptionHelp {
+        name: "snow-line",
+        value: "<0-1>",
+        help: "Fraction of max height above which the ground is snow. [default: 0.75]",
+    },
+    OptionHelp {
+        name: "rock-line",
+        value: "<0-1>",
+        help: "Fraction of max height above which the ground is bare rock. [default: 0.68]",
+    },
+    OptionHelp {
+        name: "max-slope",
+        value: "<number>",
+        help: "Steepness above which the ground is bare rock. [default: 1.5]",
+    },
+    OptionHelp {
+        name: "biome-sample-rate",
+        value: "<number>",
+        help: "How quickly the biome changes. Smaller gives larger biomes. [default: 0.001]",
+    },
+    OptionHelp {
+        name: "plant-sample-rate",
+        value: "<number>",
+        help: "How quickly plant density changes. Smaller gives larger groups. [default: 0.3]",
+    },
+    OptionHelp {
+        name: "heightmap",
+        value: "<path>",
+        help: "Read terrain height from a greyscale PGM or PNG file instead of noise.",
+    },
+    OptionHelp {
+        name: "heightmap-scale",
+        value: "<number>",
+        help: "How many voxels each heightmap pixel covers. [default: 1]",
+    },
+    OptionHelp {
+        name: "heightmap-wrap",
+        value: "<clamp|tile>",
+        help: "Extend the heightmap edges or repeat the heightmap. [default: clamp]",
+    },
+    OptionHelp {
+        name: "world",
+        value: "<infinite|finite:<width>,<depth>|island:<radius>>",
+        help: "How much of the world is generated. [default: infinite]",
+    },
+];
+
+const MAP_OPTIONS: &[OptionHelp] = &[
+    OptionHelp {
+        name: "view",
+        value: "<height|ground|biome|water|objects>",
+        help: "What the map shows. [default: ground]",
+    },
+    OptionHelp {
+        name: "origin",
+        value: "<x>,<z>",
+        help: "World coordinate of the top left corner. [default: -256,-256]",
+    },
+    OptionHelp {
+        name: "size",
+        value: "<width>,<depth>",
+        help: "Size of the map in voxels, one pixel per voxel. [default: 512,512]",
+    },
+];
+
+/// Everything that can be set from the command line or the config file.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub seed: u32,
+    pub biome_seed: Option<u32>,
+    pub settings: PathBuf,
+    pub preset: String,
+    pub render_distance: Option<i32>,
+    pub render_path: Option<RenderPath>,
+    pub window_size: IVec2,
+    pub camera: CameraMode,
+    pub position: Vec3,
+    pub sample_rate: f32,
+    pub max_height: f32,
+    pub water_level: f32,
+    pub snow_line: f32,
+    pub rock_line: f32,
+    pub max_slope: f32,
+    pub biome_sample_rate: f32,
+    pub plant_sample_rate: f32,
+    pub heightmap: Option<PathBuf>,
+    pub heightmap_scale: f32,
+    pub heightmap_wrap: HeightmapWrap,
+    pub world: WorldBounds,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            seed: 555,
+            biome_seed: None,
+            settings: PathBuf::from("settings.cfg"),
+            preset: DEFAULT_PRESET.to_string(),
+            render_distance: None,
+            render_path: None,
+            window_size: IVec2::new(800, 800),
+            camera: CameraMode::OnGround,
+            position: Vec3::ZERO,
+            sample_rate: 0.004,
+            max_height: 40.0,
+            water_level: 6.0,
+            snow_line: 0.75,
+            rock_line: 0.68,
+            max_slope: 1.5,
+            biome_sample_rate: 0.001,
+            plant_sample_rate: 0.3,
+            heightmap: None,
+            heightmap_scale: 1.0,
+            heightmap_wrap: HeightmapWrap::Clamp,
+            world: WorldBounds::Infinite,
+        }
+    }
+}
+
+impl Options {
+    /// Sets the option `name` (without leading dashes) from a string.
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "seed" => self.seed = parse(name, value)?,
+            "biome-seed" => self.biome_seed = Some(parse(name, value)?),
+            "settings" => self.settings = value.into(),
+            "preset" => self.preset = value.to_string(),
+            "render-distance" => self.render_distance = Some(parse_in(name, value, 1..=64)?),
+            "render-path" => self.render_path = Some(value.parse()?),
+            "window-size" => {
+                let size = parse_ivec2(name, value)?;
+                if size.cmplt(IVec2::ONE).any() {
+                    return Err(format!("--{name} must be positive, got {value:?}"));
+                }
+                self.window_size = size;
+            }
+            "camera" => self.camera = value.parse()?,
+            "position" => self.position = parse_vec3(name, value)?,
+            "sample-rate" => self.sample_rate = parse_positive(name, value)?,
+            "max-height" => self.max_height = parse_positive(name, value)?,
+            "water-level" => self.water_level = parse_non_negative(name, value)?,
+            "snow-line" => self.snow_line = parse_in(name, value, 0.0..=1.0)?,
+            "rock-line" => self.rock_line = parse_in(name, value, 0.0..=1.0)?,
+            "max-slope" => self.max_slope = parse_non_negative(name, value)?,
+            "biome-sample-rate" => self.biome_sample_rate = parse_positive(name, value)?,
+            "plant-sample-rate" => self.plant_sample_rate = parse_positive(name, value)?,
+            "heightmap" => self.heightmap = Some(value.into()),
+            "heightmap-scale" => self.heightmap_scale = parse_positive(name, value)?,
+            "heightmap-wrap" => self.heightmap_wrap = value.parse()?,
+            "world" => self.world = value.parse()?,
+            _ => return Err(format!("unknown option --{name}")),
+        }
+        Ok(())
+    }
+
+    /// Applies a config file with one `name = value` per line. Empty lines and lines starting
+    /// with `#` are ignored.
+    fn apply_config_file(&mut self, path: &str) -> Result<(), String> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or(format!("{path}:{}: expected `name = value`", i + 1))?;
+            let (name, value) = (name.trim(), value.trim());
+            if name == "config" {
+                return Err(format!(
+                    "{path}:{}: config files can't include others",
+                    i + 1
+                ));
+            }
+            self.set(name, value)
+                .map_err(|e| format!("{path}:{}: {e}", i + 1))?;
+        }
+        Ok(())
+    }
+
+    /// Builds the world to generate. Fails if the heightmap can't be read.
+    pub fn world(&self) -> Result<World, String> {
+        Ok(World::new(self.terrain_config()?, self.biome_config()))
+    }
+
+    fn terrain_config(&self) -> Result<TerrainConfig, String> {
+        if self.rock_line > self.snow_line {
+            return Err(format!(
+                "--rock-line ({}) must not be above --snow-line ({})",
+                self.rock_line, self.snow_line
+            ));
+        }
+        let heightmap = self
+            .heightmap
+            .as_ref()
+            .map(|path| {
+                Heightmap::load(path, self.heightmap_scale, self.heightmap_wrap)
+                    .map_err(|e| format!("{}: {e}", path.display()))
+            })
+            .transpose()?;
+        Ok(TerrainConfig {
+            sample_rate: self.sample_rate,
+            width: CHUNK_SIZE,
+            height: 20,
+            depth: CHUNK_SIZE,
+            max_height: self.max_height,
+            min_height: self.water_level,
+            snow_line: self.snow_line,
+            rock_line: self.rock_line,
+            max_slope: self.max_slope,
+            noise: Perlin::new(self.seed),
+            heightmap: heightmap.map(Arc::new),
+            bounds: self.world,
+        })
+    }
+
+    /// Loads the chosen preset from the settings file, with the command line applied on top.
+    /// If the file or the default preset doesn't exist, the defaults are used.
+    pub fn settings(&self) -> Result<Settings, String> {
+        let file = SettingsFile::load(&self.settings)?;
+        let mut settings = match file.get(&self.preset) {
+            Some(settings) => settings,
+            None if self.preset == DEFAULT_PRESET => Settings::default(),
+            None => {
+                return Err(format!(
+                    "no preset named {:?} in {}",
+                    self.preset,
+                    self.settings.display()
+                ))
+            }
+        };
+        if let Some(render_distance) = self.render_distance {
+            settings.render_distance = render_distance;
+        }
+        if let Some(render_path) = self.render_path {
+            settings.render_path = render_path;
+        }
+        Ok(settings)
+    }
+
+    fn biome_config(&self) -> BiomeConfig {
+        BiomeConfig {
+            biome_sample_rate: self.biome_sample_rate,
+            plant_sample_rate: self.plant_sample_rate,
+            noise: Perlin::new(self.biome_seed.unwrap_or(self.seed.wrapping_add(111))),
+        }
+    }
+}
+
+/// What the program was asked to do.
+pub enum Command {
+    Help,
+    /// Open the window and walk around.
+    Viewer(Options),
+    /// Render a map of the world to a file.
+    Map(Options, MapExport),
+}
+
+/// Parses the command line arguments, not including the program name.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let args: Vec<String> = args.into_iter().collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return Ok(Command::Help);
+    }
+
+    let mut options = Options::default();
+    // The config file is applied first so the command line can override it
+    if let Some(i) = args.iter().position(|arg| arg == "--config") {
+        let path = args.get(i + 1).ok_or("missing value for --config")?;
+        options.apply_config_file(path)?;
+    }
+
+    let mut map: Option<MapExport> = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "map" && map.is_none() {
+            let output = args.next().ok_or("missing output file for map")?;
+            map = Some(MapExport::new(output.into()));
+            continue;
+        }
+        let name = arg
+            .strip_prefix("--")
+            .ok_or(format!("unexpected argument {arg:?}"))?;
+        let value = args.next().ok_or(format!("missing value for {arg}"))?;
+        match (name, &mut map) {
+            ("config", _) => {}
+            ("view", Some(map)) => map.view = value.parse()?,
+            ("origin", Some(map)) => map.origin = parse_ivec2(name, &value)?,
+            ("size", Some(map)) => {
+                let size = parse_ivec2(name, &value)?;
+                if size.cmplt(IVec2::ONE).any() {
+                    return Err(format!("--{name} must be positive, got {value:?}"));
+                }
+                map.size = size;
+            }
+            _ => options.set(name, &value)?,
+        }
+    }
+
+    Ok(match map {
+        Some(map) => Command::Map(options, map),
+        None => Command::Viewer(options),
+    })
+}
+
+pub fn help() -> String {
+    let mut help = String::new();
+    let options = |help: &mut String, options: &[OptionHelp]| {
+        for option in options {
+            writeln!(help, "  --{} {}", option.name, option.value).unwrap();
+            writeln!(help, "          {}", option.help).unwrap();
+        }
+    };
+
+    help.push_str(
+        "Walk around in a procedurally generated voxel garden.\n\n\
+         Usage: project [OPTIONS]\n       \
+         project [OPTIONS] map <output.png|output.ppm> [MAP OPTIONS]\n\n\
+         Options:\n",
+    );
+    options(&mut help, OPTIONS);
+    help.push_str("  -h, --help\n          Print this help.\n\nMap options:\n");
+    options(&mut help, MAP_OPTIONS);
+    help
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| {
+        let expected = OPTIONS
+            .iter()
+            .find(|option| option.name == name)
+            .map_or("a number", |option| option.value);
+        format!("invalid value {value:?} for --{name}, expected {expected}")
+    })
+}
+
+fn parse_in<T: FromStr + PartialOrd + std::fmt::Display>(
+    name: &str,
+    value: &str,
+    range: std::ops::RangeInclusive<T>,
+) -> Result<T, String> {
+    let v: T = parse(name, value)?;
+    if !range.contains(&v) {
+        return Err(format!(
+            "--{name} must be between {} and {}, got {value}",
+            range.start(),
+            range.end()
+        ));
+    }
+    Ok(v)
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<f32, String> {
+    let v: f32 = parse(name, value)?;
+    if !(v > 0.0 && v.is_finite()) {
+        return Err(format!("--{name} must be positive, got {value}"));
+    }
+    Ok(v)
+}
+
+fn parse_non_negative(name: &str, value: &str) -> Result<f32, String> {
+    let v: f32 = parse(name, value)?;
+    if !(v >= 0.0 && v.is_finite()) {
+        return Err(format!("--{name} must not be negative, got {value}"));
+    }
+    Ok(v)
+}
+
+fn parse_ivec2(name: &str, value: &str) -> Result<IVec2, String> {
+    value
+        .split_once(',')
+        .and_then(|(x, y)| Some(IVec2::new(x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .ok_or(format!(
+            "invalid value {value:?} for --{name}, expected two integers like 10,20"
+        ))
+}
+
+fn parse_vec3(name: &str, value: &str) -> Result<Vec3, String> {
+    let parts: Vec<f32> = value
+        .split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| {
+            format!("invalid value {value:?} for --{name}, expected three numbers like 0,20,0")
+        })?;
+    match parts[..] {
+        [x, y, z] if parts.iter().all(|v| v.is_finite()) => Ok(Vec3::new(x, y, z)),
+        [_, _, _] => Err(format!("--{name} must be finite, got {value}")),
+        _ => Err(format!(
+            "invalid value {value:?} for --{name}, expected three numbers like 0,20,0"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_must_be_finite() {
+        let mut options = Options::default();
+        options.set("position", "1, 2.5,-3").unwrap();
+        assert_eq!(options.position, Vec3::new(1.0, 2.5, -3.0));
+        assert!(options.set("position", "nan,0,0").is_err());
+        assert!(options.set("position", "0,inf,0").is_err());
+        assert!(options.set("position", "0,0,-inf").is_err());
+        assert!(options.set("position", "0,0").is_err());
+    }
+
+    #[test]
+    fn heightmap_wrap() {
+        let mut options = Options::default();
+        options.set("heightmap-wrap", "tile").unwrap();
+        assert_eq!(options.heightmap_wrap, HeightmapWrap::Tile);
+        options.set("heightmap-wrap", "clamp").unwrap();
+        assert_eq!(options.heightmap_wrap, HeightmapWrap::Clamp);
+        assert!(options.set("heightmap-wrap", "mirror").is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! The world clock, and the sun, moon and colours of the sky that follow from it.
+//!
+//! The time of day goes from 0 to 1: midnight at 0, sunrise at 0.25, noon at 0.5 and sunset at
+//! 0.75. Colours are interpolated between keyframes at fixed times of day.
+
+use std::f32::consts::TAU;
+
+use glam::{Vec3, Vec4};
+
+/// How far the sun's path is tilted away from straight overhead, so shadows at noon aren't
+/// straight down.
+const SUN_TILT: f32 = 0.25;
+
+const NIGHT_SKY: Vec3 = Vec3::new(0.03, 0.04, 0.09);
+const NIGHT_ZENITH: Vec3 = Vec3::new(0.005, 0.008, 0.03);
+/// The moon is the light source at night.
+const NIGHT_LIGHT: Vec3 = Vec3::new(0.15, 0.18, 0.3);
+const NIGHT_AMBIENT: Vec3 = Vec3::new(0.12, 0.14, 0.25);
+const TWILIGHT_SKY: Vec3 = Vec3::new(0.9, 0.45, 0.3);
+const TWILIGHT_ZENITH: Vec3 = Vec3::new(0.2, 0.22, 0.4);
+const TWILIGHT_AMBIENT: Vec3 = Vec3::new(0.35, 0.3, 0.35);
+const LOW_SUN_SKY: Vec3 = Vec3::new(0.75, 0.7, 0.7);
+const LOW_SUN_ZENITH: Vec3 = Vec3::new(0.35, 0.5, 0.8);
+const LOW_SUN_LIGHT: Vec3 = Vec3::new(1.0, 0.5, 0.2);
+const LOW_SUN_AMBIENT: Vec3 = Vec3::new(0.5, 0.45, 0.45);
+/// 0x87CEEB
+const DAY_SKY: Vec3 = Vec3::new(
+    0x87 as f32 / 255.0,
+    0xCE as f32 / 255.0,
+    0xEB as f32 / 255.0,
+);
+const DAY_ZENITH: Vec3 = Vec3::new(0.25, 0.5, 0.9);
+
+/// The colours at one time of day.
+#[derive(Clone, Copy)]
+struct Keyframe {
+    time: f32,
+    sky: Vec3,
+    zenith: Vec3,
+    stars: f32,
+    light: Vec3,
+    ambient: Vec3,
+}
+
+/// Light and colours at a time of day.
+pub struct Daylight {
+    /// Colour of the sky at the horizon, which is what the world fades into.
+    pub sky_color: Vec3,
+    /// Colour of the sky straight up.
+    pub zenith_color: Vec3,
+    /// From 0 during the day to 1 at night.
+    pub star_visibility: f32,
+    /// Direction towards the sun during the day and the moon at night.
+    pub light_direction: Vec3,
+    pub light_color: Vec4,
+    pub ambient_color: Vec4,
+}
+
+pub struct WorldClock {
+    /// From 0 to 1, see the module documentation.
+    pub time_of_day: f32,
+    /// Length of a day in seconds at normal speed.
+    pub day_length: f32,
+    /// How many times faster than normal the clock runs.
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl WorldClock {
+    pub fn new() -> Self {
+        WorldClock {
+            // Mid-morning, so starting the program doesn't show the night
+            time_of_day: 0.4,
+            day_length: 600.0,
+            speed: 1.0,
+            paused: false,
+        }
+    }
+
+    /// Moves the clock forward by `delta` seconds.
+    pub fn advance(&mut self, delta: f32) {
+        if self.paused || self.day_length <= 0.0 {
+            return;
+        }
+        self.time_of_day =
+            (self.time_of_day + delta * self.speed / self.day_length).rem_euclid(1.0);
+    }
+
+    /// Direction towards the sun. It rises in positive x, is highest at noon and sets in
+    /// negative x.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = self.sky_angle();
+        Vec3::new(angle.cos(), angle.sin(), SUN_TILT).normalize()
+    }
+
+    /// How far the sky has turned around the z axis since sunrise, in radians.
+    pub fn sky_angle(&self) -> f32 {
+        (self.time_of_day - 0.25) * TAU
+    }
+
+    /// Direction towards the moon, always on the other side of the sky from the sun.
+    pub fn moon_direction(&self) -> Vec3 {
+        let sun = self.sun_direction();
+        Vec3::new(-sun.x, -sun.y, sun.z)
+    }
+
+    /// Light and colours at the current time of day. `sun_color` and `ambient_color` are the
+    /// colours during the day.
+    pub fn daylight(&self, sun_color: Vec4, ambient_color: Vec4) -> Daylight {
+        let night = |time| Keyframe {
+            time,
+            sky: NIGHT_SKY,
+            zenith: NIGHT_ZENITH,
+            stars: 1.0,
+            light: NIGHT_LIGHT,
+            ambient: NIGHT_AMBIENT,
+        };
+        let day = |time| Keyframe {
+            time,
+            sky: DAY_SKY,
+            zenith: DAY_ZENITH,
+            stars: 0.0,
+            light: sun_color.truncate(),
+            ambient: ambient_color.truncate(),
+        };
+        // The light is black when the sun and moon cross the horizon, so switching between
+        // them can't be seen
+        let twilight = |time| Keyframe {
+            time,
+            sky: TWILIGHT_SKY,
+            zenith: TWILIGHT_ZENITH,
+            stars: 0.3,
+            light: Vec3::ZERO,
+            ambient: TWILIGHT_AMBIENT,
+        };
+        let low_sun = |time| Keyframe {
+            time,
+            sky: LOW_SUN_SKY,
+            zenith: LOW_SUN_ZENITH,
+            stars: 0.0,
+            light: LOW_SUN_LIGHT,
+            ambient: LOW_SUN_AMBIENT,
+        };
+        let keyframes = [
+            night(0.0),
+            night(0.21),
+            // Dawn
+            twilight(0.25),
+            low_sun(0.3),
+            day(0.36),
+            day(0.64),
+            // Dusk
+            low_sun(0.7),
+            twilight(0.75),
+            night(0.79),
+            night(1.0),
+        ];
+
+        let t = self.time_of_day.rem_euclid(1.0);
+        let i = keyframes
+            .windows(2)
+            .position(|pair| t <= pair[1].time)
+            .unwrap_or(keyframes.len() - 2);
+        let (a, b) = (keyframes[i], keyframes[i + 1]);
+        let s = ((t - a.time) / (b.time - a.time)).clamp(0.0, 1.0);
+        // Ease in and out, so the colours don't change speed abruptly at keyframes
+        let s = s * s * (3.0 - 2.0 * s);
+
+        let sun = self.sun_direction();
+        Daylight {
+            sky_color: a.sky.lerp(b.sky, s),
+            zenith_color: a.zenith.lerp(b.zenith, s),
+            star_visibility: a.stars + (b.stars - a.stars) * s,
+            light_direction: if sun.y >= 0.0 {
+                sun
+            } else {
+                self.moon_direction()
+            },
+            light_color: a.light.lerp(b.light, s).extend(1.0),
+            ambient_color: a.ambient.lerp(b.ambient, s).extend(1.0),
+        }
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn egui_rows(&mut self, ui: &mut egui::Ui) {
+        ui.label("time of day");
+        ui.horizontal(|ui| {
+            let mut hours = self.time_of_day * 24.0;
+            let slider = egui::Slider::new(&mut hours, (0.0)..=24.0)
+                .clamp_to_range(true)
+                .custom_formatter(|hours, _| {
+                    let minutes = (hours * 60.0) as u32;
+                    format!("{:02}:{:02}", minutes / 60 % 24, minutes % 60)
+                });
+            if ui.add(slider).changed() {
+                self.time_of_day = (hours / 24.0).rem_euclid(1.0);
+            }
+            ui.checkbox(&mut self.paused, "paused");
+        });
+        ui.end_row();
+
+        ui.label("day length (seconds)");
+        ui.add(
+            egui::Slider::new(&mut self.day_length, (10.0)..=3600.0)
+                .clamp_to_range(true)
+                .logarithmic(true),
+        );
+        ui.end_row();
+
+        ui.label("time speed");
+        ui.add(
+            egui::Slider::new(&mut self.speed, (0.1)..=100.0)
+                .clamp_to_range(true)
+                .logarithmic(true),
+        );
+        ui.end_row();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUN: Vec4 = Vec4::new(1.0, 0.9, 0.8, 1.0);
+    const AMBIENT: Vec4 = Vec4::new(0.4, 0.4, 0.5, 1.0);
+
+    fn at(time_of_day: f32) -> WorldClock {
+        WorldClock {
+            time_of_day,
+            ..WorldClock::new()
+        }
+    }
+
+    #[test]
+    fn noon_is_day() {
+        let clock = at(0.5);
+        let daylight = clock.daylight(SUN, AMBIENT);
+        assert_eq!(daylight.sky_color, DAY_SKY);
+        assert_eq!(daylight.zenith_color, DAY_ZENITH);
+        assert_eq!(daylight.star_visibility, 0.0);
+        assert_eq!(daylight.light_color, SUN);
+        assert_eq!(daylight.ambient_color, AMBIENT);
+        assert_eq!(daylight.light_direction, clock.sun_direction());
+    }
+
+    #[test]
+    fn midnight_is_night() {
+        for time in [0.0, 1.0] {
+            let clock = at(time);
+            let daylight = clock.daylight(SUN, AMBIENT);
+            assert_eq!(daylight.sky_color, NIGHT_SKY);
+            assert_eq!(daylight.star_visibility, 1.0);
+            assert_eq!(daylight.light_color, NIGHT_LIGHT.extend(1.0));
+            assert_eq!(daylight.ambient_color, NIGHT_AMBIENT.extend(1.0));
+            assert_eq!(daylight.light_direction, clock.moon_direction());
+        }
+    }
+
+    #[test]
+    fn colours_are_interpolated_between_keyframes() {
+        // Halfway between twilight and the low sun
+        let daylight = at(0.275).daylight(SUN, AMBIENT);
+        let sky = TWILIGHT_SKY.lerp(LOW_SUN_SKY, 0.5);
+        assert!(daylight.sky_color.abs_diff_eq(sky, 1e-5));
+        assert!((daylight.star_visibility - 0.15).abs() < 1e-5);
+        // Eased, so the colours barely change near a keyframe
+        let daylight = at(0.255).daylight(SUN, AMBIENT);
+        let sky = TWILIGHT_SKY.lerp(LOW_SUN_SKY, 0.028);
+        assert!(daylight.sky_color.abs_diff_eq(sky, 1e-5));
+    }
+
+    #[test]
+    fn the_clock_wraps_at_the_end_of_the_day() {
+        let mut clock = at(0.95);
+        clock.day_length = 100.0;
+        clock.advance(10.0);
+        assert!((clock.time_of_day - 0.05).abs() < 1e-5);
+        // Exactly the end of the day is midnight again
+        let mut clock = at(0.5);
+        clock.day_length = 4.0;
+        clock.speed = 2.0;
+        clock.advance(1.0);
+        assert_eq!(clock.time_of_day, 0.0);
+    }
+
+    #[test]
+    fn a_paused_clock_stands_still() {
+        let mut clock = at(0.3);
+        clock.paused = true;
+        clock.advance(100.0);
+        assert_eq!(clock.time_of_day, 0.3);
+    }
+
+    #[test]
+    fn sun_and_moon_are_on_opposite_sides() {
+        let tilt = Vec3::new(0.0, 0.0, SUN_TILT);
+        let sunrise = at(0.25).sun_direction();
+        assert!(sunrise.abs_diff_eq((Vec3::X + tilt).normalize(), 1e-5));
+        let noon = at(0.5).sun_direction();
+        assert!(noon.abs_diff_eq((Vec3::Y + tilt).normalize(), 1e-5));
+        let sunset = at(0.75).sun_direction();
+        assert!(sunset.abs_diff_eq((Vec3::NEG_X + tilt).normalize(), 1e-5));
+
+        for time in [0.0, 0.1, 0.5, 0.8] {
+            let clock = at(time);
+            let (sun, moon) = (clock.sun_direction(), clock.moon_direction());
+            assert!((moon.length() - 1.0).abs() < 1e-5);
+            assert!(moon.abs_diff_eq(Vec3::new(-sun.x, -sun.y, sun.z), 1e-6));
+        }
+        let midnight = at(0.0).moon_direction();
+        assert!(midnight.abs_diff_eq((Vec3::Y + tilt).normalize(), 1e-5));
+    }
+}
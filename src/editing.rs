@@ -0,0 +1,216 @@
+//! Interactive editing of the world: breaking and placing voxels and planting objects where
+//! the camera is looking.
+
+use glam::{IVec3, Mat4, Vec3};
+
+use voxel_garden::chunks::chunk_of;
+use voxel_garden::models::Generator;
+use voxel_garden::raycast::RayHit;
+use voxel_garden::utils::{BROWN, WHITE};
+use voxel_garden::voxels::Material;
+use voxel_garden::{Chunks, InstanceData};
+
+use crate::history::History;
+
+/// What the mouse buttons do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+    /// Left-click breaks a voxel, right-click places one.
+    Voxels,
+    /// Left-click removes an object, right-click plants a new one.
+    Plant(Generator),
+}
+
+impl Tool {
+    /// Every tool, in the order they are cycled through.
+    fn all() -> impl Iterator<Item = Tool> {
+        std::iter::once(Tool::Voxels).chain(Generator::ALL.into_iter().map(Tool::Plant))
+    }
+
+    #[cfg(feature = "egui")]
+    fn name(&self) -> &'static str {
+        match self {
+            Tool::Voxels => "voxels",
+            Tool::Plant(generator) => generator.name(),
+        }
+    }
+}
+
+pub struct Editor {
+    /// The voxel the crosshair points at, updated every frame.
+    pub target: Option<RayHit>,
+    pub tool: Tool,
+    /// Material placed with the right mouse button.
+    pub material: Material,
+    /// Seed of planted objects.
+    pub seed: u64,
+    /// How far away voxels can be edited.
+    pub reach: f32,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor {
+            target: None,
+            tool: Tool::Voxels,
+            material: Material::new(BROWN),
+            seed: 0,
+            reach: 50.0,
+        }
+    }
+
+    pub fn update_target(&mut self, chunks: &Chunks, origin: Vec3, direction: Vec3) {
+        self.target = chunks.raycast(origin, direction, self.reach);
+    }
+
+    /// Switches to the next tool, wrapping around after the last one.
+    pub fn next_tool(&mut self) {
+        let tools: Vec<Tool> = Tool::all().collect();
+        let current = tools.iter().position(|&t| t == self.tool).unwrap_or(0);
+        self.tool = tools[(current + 1) % tools.len()];
+    }
+
+    pub fn left_click(&self, chunks: &mut Chunks, history: &mut History) {
+        match self.tool {
+            Tool::Voxels => self.break_voxel(chunks, history),
+            Tool::Plant(_) => self.remove_object(chunks, history),
+        }
+    }
+
+    pub fn right_click(&self, chunks: &mut Chunks, history: &mut History) {
+        match self.tool {
+            Tool::Voxels => self.place_voxel(chunks, history),
+            Tool::Plant(generator) => self.plant(chunks, history, generator),
+        }
+    }
+
+    /// Removes the targeted voxel.
+    fn break_voxel(&self, chunks: &mut Chunks, history: &mut History) {
+        if let Some(target) = self.target {
+            history.set_voxel(chunks, target.voxel, None);
+        }
+    }
+
+    /// Places a voxel against the targeted face.
+    fn place_voxel(&self, chunks: &mut Chunks, history: &mut History) {
+        let Some(target) = self.target else {
+            return;
+        };
+        // The ray started inside the voxel, so there is no face to place against
+        if target.normal == Vec3::ZERO {
+            return;
+        }
+        let position = target.voxel + target.normal.round().as_ivec3();
+        if chunks.get_voxel(position).is_none() {
+            history.set_voxel(chunks, position, Some(self.material));
+        }
+    }
+
+    /// Plants an object on the ground at the targeted position. When aiming at a wall, the
+    /// object falls down to the ground below it.
+    fn plant(&self, chunks: &mut Chunks, history: &mut History, generator: Generator) {
+        let Some(target) = self.target else {
+            return;
+        };
+        let mut base = target.voxel + target.normal.round().as_ivec3();
+        for _ in 0..MAX_FALL {
+            if chunks.get_voxel(base - IVec3::Y).is_some() {
+                let object = generator.generate(self.seed, base.as_vec3());
+                history.add_object(chunks, chunk_of(base), object);
+                return;
+            }
+            base.y -= 1;
+        }
+    }
+
+    /// Removes the targeted object.
+    fn remove_object(&self, chunks: &mut Chunks, history: &mut History) {
+        if let Some(object) = self.target.and_then(|target| target.object) {
+            history.remove_object(chunks, object);
+        }
+    }
+
+    /// The targeted voxel drawn a little larger and brighter than the real one, and the model
+    /// matrix (without the camera) to draw it with.
+    pub fn highlight(&self, chunks: &Chunks) -> Option<(InstanceData, Mat4)> {
+        let voxel = self.target?.voxel;
+        let material = chunks.get_voxel(voxel)?;
+        let position = voxel.as_vec3();
+        // Scale around the voxel's center so the highlight covers it from every side
+        let model = Mat4::from_translation(position)
+            * Mat4::from_scale(Vec3::splat(1.05))
+            * Mat4::from_translation(-position);
+        let instance = InstanceData {
+            position,
+            color: material.color.lerp(WHITE, 0.5),
+            is_water: 0,
+        };
+        Some((instance, model))
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn egui_rows(&mut self, ui: &mut egui::Ui) {
+        use egui::color_picker::color_edit_button_rgb;
+
+        ui.label("tool");
+        egui::ComboBox::from_id_source("tool")
+            .selected_text(self.tool.name())
+            .show_ui(ui, |ui| {
+                for tool in Tool::all() {
+                    ui.selectable_value(&mut self.tool, tool, tool.name());
+                }
+            });
+        ui.end_row();
+
+        ui.label("seed");
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.seed));
+            if ui.button("random").clicked() {
+                self.seed = rand::random();
+            }
+        });
+        ui.end_row();
+
+        ui.label("block color");
+        let color = &mut self.material.color;
+        let mut rgb = [color.x, color.y, color.z];
+        color_edit_button_rgb(ui, &mut rgb);
+        *color = glam::Vec4::new(rgb[0], rgb[1], rgb[2], 1.0);
+        ui.end_row();
+
+        ui.label("reach");
+        ui.add(egui::Slider::new(&mut self.reach, (5.0)..=200.0).clamp_to_range(true));
+        ui.end_row();
+    }
+}
+
+/// How far down a planted object can fall to reach the ground.
+const MAX_FALL: i32 = 64;
+
+/// A small plus in the middle of the screen, in view space. Draw it with `crosshair_matrix`
+/// as model matrix and no camera.
+pub fn crosshair() -> Vec<InstanceData> {
+    [
+        IVec3::ZERO,
+        IVec3::X,
+        IVec3::NEG_X,
+        IVec3::Y,
+        IVec3::NEG_Y,
+        2 * IVec3::X,
+        -2 * IVec3::X,
+        2 * IVec3::Y,
+        -2 * IVec3::Y,
+    ]
+    .into_iter()
+    .map(|p| InstanceData {
+        position: p.as_vec3(),
+        color: WHITE,
+        is_water: 0,
+    })
+    .collect()
+}
+
+/// Places the crosshair just in front of the camera, with every voxel a few pixels wide.
+pub fn crosshair_matrix() -> Mat4 {
+    Mat4::from_translation(Vec3::new(0.0, 0.0, -1.0)) * Mat4::from_scale(Vec3::splat(0.004))
+}
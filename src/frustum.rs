@@ -0,0 +1,126 @@
+//! View frustum culling of chunks using axis-aligned bounding boxes.
+
+use glam::{Mat4, Vec3, Vec4};
+
+/// An axis-aligned bounding box in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The smallest box containing every voxel centered at `positions`, or `None` if there are
+    /// none.
+    pub fn around_voxels(positions: impl IntoIterator<Item = Vec3>) -> Option<Aabb> {
+        let mut positions = positions.into_iter();
+        let first = positions.next()?;
+        let (min, max) = positions.fold((first, first), |(min, max), p| (min.min(p), max.max(p)));
+        Some(Aabb {
+            min: min - 0.5,
+            max: max + 0.5,
+        })
+    }
+}
+
+/// The six planes of the volume a camera can see.
+pub struct Frustum {
+    /// Planes as (normal, distance), with the normal pointing into the frustum.
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view-projection matrix, using the method from "Fast
+    /// Extraction of Viewing Frustum Planes from the World-View-Projection Matrix" by Gribb and
+    /// Hartmann. Points inside the frustum end up between -w and w on every axis in clip
+    /// space, and every such inequality is a plane.
+    pub fn from_matrix(view_projection: Mat4) -> Frustum {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+        Frustum {
+            planes: [w + x, w - x, w + y, w - y, w + z, w - z],
+        }
+    }
+
+    /// Whether any part of the box might be visible. Boxes that are close to a corner of the
+    /// frustum can be let through even though they are outside it, but visible boxes are never
+    /// rejected.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner of the box furthest along the plane's normal
+            let normal = plane.truncate();
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A camera at the origin looking down negative z, seeing 90° up, down and to the sides.
+    fn camera() -> Frustum {
+        let projection = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        Frustum::from_matrix(projection * Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y))
+    }
+
+    fn cube(center: Vec3, half_size: f32) -> Aabb {
+        Aabb {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+
+    #[test]
+    fn boxes_in_view_intersect() {
+        let frustum = camera();
+        assert!(frustum.intersects(&cube(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects(&cube(Vec3::new(8.0, -8.0, -10.0), 1.0)));
+        // Surrounding the camera
+        assert!(frustum.intersects(&cube(Vec3::ZERO, 5.0)));
+    }
+
+    #[test]
+    fn boxes_partly_in_view_intersect() {
+        let frustum = camera();
+        // Reaching across the right plane, which is at x = 10 this far away
+        assert!(frustum.intersects(&cube(Vec3::new(10.5, 0.0, -10.0), 1.0)));
+        // Reaching across the far plane
+        assert!(frustum.intersects(&cube(Vec3::new(0.0, 0.0, -100.5), 1.0)));
+    }
+
+    #[test]
+    fn boxes_out_of_view_dont_intersect() {
+        let frustum = camera();
+        // Behind the camera
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, 0.0, 10.0), 1.0)));
+        // Beyond the far plane
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, 0.0, -110.0), 1.0)));
+        // To the left, above and below
+        assert!(!frustum.intersects(&cube(Vec3::new(-20.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, 20.0, -10.0), 1.0)));
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, -20.0, -10.0), 1.0)));
+    }
+
+    #[test]
+    fn orthographic_frustums_are_boxes() {
+        let projection = Mat4::orthographic_rh_gl(-10.0, 10.0, -10.0, 10.0, 0.0, 50.0);
+        let frustum = Frustum::from_matrix(projection);
+        assert!(frustum.intersects(&cube(Vec3::new(9.0, -9.0, -49.0), 0.5)));
+        assert!(!frustum.intersects(&cube(Vec3::new(11.0, 0.0, -20.0), 0.5)));
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, 0.0, -51.0), 0.5)));
+    }
+
+    #[test]
+    fn boxes_around_voxels_cover_the_whole_voxels() {
+        let aabb = Aabb::around_voxels([Vec3::new(1.0, 2.0, 3.0), Vec3::new(-1.0, 5.0, 0.0)]);
+        assert_eq!(
+            aabb,
+            Some(Aabb {
+                min: Vec3::new(-1.5, 1.5, -0.5),
+                max: Vec3::new(1.5, 5.5, 3.5),
+            })
+        );
+        assert_eq!(Aabb::around_voxels([]), None);
+    }
+}
@@ -0,0 +1,442 @@
+//! Undo and redo of world edits.
+//!
+//! Every change to the world made through [`History`] is recorded together with what it
+//! replaced, so it can be reverted. Changes are grouped into strokes, one per mouse click, and
+//! undo and redo work on whole strokes.
+
+use std::collections::VecDeque;
+
+use glam::{IVec2, IVec3};
+
+use voxel_garden::chunks::ObjectId;
+use voxel_garden::terrain::Object;
+use voxel_garden::voxels::Material;
+use voxel_garden::{Chunks, Model};
+
+/// How many voxels the history can hold before the oldest strokes are forgotten. Objects count
+/// as the number of voxels in them.
+const MAX_VOXELS: usize = 1_000_000;
+
+enum Change {
+    Voxel {
+        position: IVec3,
+        before: Option<Material>,
+        /// Whether `before` was an edit rather than what the world generated.
+        was_edited: bool,
+        after: Option<Material>,
+    },
+    ObjectAdded {
+        chunk: IVec2,
+        object: Object,
+        /// Edits the object replaced.
+        replaced: Vec<(IVec3, Option<Material>)>,
+    },
+    ObjectRemoved {
+        chunk: IVec2,
+        object: Object,
+    },
+}
+
+impl Change {
+    fn size(&self) -> usize {
+        match self {
+            Change::Voxel { .. } => 1,
+            Change::ObjectAdded { object, .. } | Change::ObjectRemoved { object, .. } => {
+                object.models.iter().map(|model| model.points.len()).sum()
+            }
+        }
+    }
+
+    fn apply(&self, chunks: &mut Chunks) {
+        match self {
+            Change::Voxel {
+                position, after, ..
+            } => chunks.restore_voxel(*position, *after, true),
+            Change::ObjectAdded { chunk, object, .. } => {
+                chunks.add_object(*chunk, object.clone());
+            }
+            Change::ObjectRemoved { chunk, object } => {
+                chunks.remove_object(ObjectId {
+                    chunk: *chunk,
+                    id: object.id,
+                });
+            }
+        }
+    }
+
+    fn revert(&self, chunks: &mut Chunks) {
+        match self {
+            Change::Voxel {
+                position,
+                before,
+                was_edited,
+                ..
+            } => chunks.restore_voxel(*position, *before, *was_edited),
+            Change::ObjectAdded {
+                chunk,
+                object,
+                replaced,
+            } => {
+                chunks.remove_object(ObjectId {
+                    chunk: *chunk,
+                    id: object.id,
+                });
+                for &(position, material) in replaced {
+                    chunks.restore_voxel(position, material, true);
+                }
+            }
+            Change::ObjectRemoved { chunk, object } => {
+                chunks.restore_object(*chunk, object.clone());
+            }
+        }
+    }
+}
+
+/// The changes made by one stroke.
+struct Stroke {
+    changes: Vec<Change>,
+}
+
+impl Stroke {
+    fn size(&self) -> usize {
+        self.changes.iter().map(Change::size).sum()
+    }
+
+    /// Short description for the history list.
+    #[cfg(feature = "egui")]
+    fn describe(&self) -> String {
+        let (mut voxels, mut added, mut removed) = (0, 0, 0);
+        for change in &self.changes {
+            match change {
+                Change::Voxel { .. } => voxels += 1,
+                Change::ObjectAdded { .. } => added += 1,
+                Change::ObjectRemoved { .. } => removed += 1,
+            }
+        }
+        let mut parts = Vec::new();
+        if voxels > 0 {
+            parts.push(format!(
+                "{voxels} voxel{}",
+                if voxels == 1 { "" } else { "s" }
+            ));
+        }
+        if added > 0 {
+            parts.push(format!("planted {added}"));
+        }
+        if removed > 0 {
+            parts.push(format!("removed {removed}"));
+        }
+        parts.join(", ")
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    /// Strokes that can be undone, oldest first.
+    undo: VecDeque<Stroke>,
+    /// Strokes that have been undone, most recently undone last.
+    redo: Vec<Stroke>,
+    /// The stroke being made, if any.
+    current: Option<Stroke>,
+    /// Total size of all strokes in `undo` and `redo`.
+    size: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Starts a new stroke. Changes until `end_stroke` are undone together.
+    pub fn begin_stroke(&mut self) {
+        self.end_stroke();
+        self.current = Some(Stroke {
+            changes: Vec::new(),
+        });
+    }
+
+    pub fn end_stroke(&mut self) {
+        let Some(stroke) = self.current.take() else {
+            return;
+        };
+        if stroke.changes.is_empty() {
+            return;
+        }
+        // A new change makes the undone strokes unreachable
+        self.size -= self.redo.drain(..).map(|s| s.size()).sum::<usize>();
+        self.size += stroke.size();
+        self.undo.push_back(stroke);
+        while self.size > MAX_VOXELS && self.undo.len() > 1 {
+            let oldest = self.undo.pop_front().unwrap();
+            self.size -= oldest.size();
+        }
+    }
+
+    fn record(&mut self, change: Change) {
+        match &mut self.current {
+            Some(stroke) => stroke.changes.push(change),
+            // Changes outside of a stroke are strokes of their own
+            None => {
+                self.current = Some(Stroke {
+                    changes: vec![change],
+                });
+                self.end_stroke();
+            }
+        }
+    }
+
+    pub fn set_voxel(&mut self, chunks: &mut Chunks, position: IVec3, material: Option<Material>) {
+        let before = chunks.get_voxel(position);
+        if before == material {
+            return;
+        }
+        let change = Change::Voxel {
+            position,
+            before,
+            was_edited: chunks.is_edited(position),
+            after: material,
+        };
+        change.apply(chunks);
+        self.record(change);
+    }
+
+    pub fn add_object(&mut self, chunks: &mut Chunks, chunk: IVec2, models: Vec<Model>) {
+        let object = chunks.new_object(models);
+        let replaced = chunks.add_object(chunk, object.clone());
+        self.record(Change::ObjectAdded {
+            chunk,
+            object,
+            replaced,
+        });
+    }
+
+    pub fn remove_object(&mut self, chunks: &mut Chunks, id: ObjectId) {
+        if let Some(object) = chunks.remove_object(id) {
+            self.record(Change::ObjectRemoved {
+                chunk: id.chunk,
+                object,
+            });
+        }
+    }
+
+    pub fn undo(&mut self, chunks: &mut Chunks) {
+        self.end_stroke();
+        if let Some(stroke) = self.undo.pop_back() {
+            for change in stroke.changes.iter().rev() {
+                change.revert(chunks);
+            }
+            self.redo.push(stroke);
+        }
+    }
+
+    pub fn redo(&mut self, chunks: &mut Chunks) {
+        self.end_stroke();
+        if let Some(stroke) = self.redo.pop() {
+            for change in &stroke.changes {
+                change.apply(chunks);
+            }
+            self.undo.push_back(stroke);
+        }
+    }
+
+    /// Descriptions of every stroke, oldest first, and how many of them are done. The rest
+    /// have been undone and can be redone.
+    #[cfg(feature = "egui")]
+    pub fn entries(&self) -> (Vec<String>, usize) {
+        let entries = self
+            .undo
+            .iter()
+            .chain(self.redo.iter().rev())
+            .map(Stroke::describe)
+            .collect();
+        (entries, self.undo.len())
+    }
+
+    /// Undoes or redoes strokes until `done` strokes are done.
+    #[cfg(feature = "egui")]
+    pub fn go_to(&mut self, chunks: &mut Chunks, done: usize) {
+        while self.undo.len() > done && !self.undo.is_empty() {
+            self.undo(chunks);
+        }
+        while self.undo.len() < done && !self.redo.is_empty() {
+            self.redo(chunks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3, Vec4};
+    use noise::Perlin;
+    use voxel_garden::biomes::BiomeConfig;
+    use voxel_garden::terrain::{TerrainConfig, WorldBounds};
+    use voxel_garden::{InstanceData, World, CHUNK_SIZE};
+
+    use super::*;
+
+    const STONE: Material = Material {
+        color: Vec4::new(0.5, 0.5, 0.5, 1.0),
+        is_water: false,
+    };
+    const WOOD: Material = Material {
+        color: Vec4::new(0.4, 0.25, 0.1, 1.0),
+        is_water: false,
+    };
+
+    /// Chunks with the chunk at the origin generated.
+    fn chunks() -> Chunks {
+        let world = World::new(
+            TerrainConfig {
+                sample_rate: 0.004,
+                width: CHUNK_SIZE,
+                height: 20,
+                depth: CHUNK_SIZE,
+                max_height: 40.0,
+                min_height: 6.0,
+                snow_line: 0.75,
+                rock_line: 0.68,
+                max_slope: 1.5,
+                noise: Perlin::new(555),
+                heightmap: None,
+                bounds: WorldBounds::Infinite,
+            },
+            BiomeConfig {
+                noise: Perlin::new(666),
+                biome_sample_rate: 0.001,
+                plant_sample_rate: 0.3,
+            },
+        );
+        let mut chunks = Chunks::new();
+        chunks.insert(IVec2::ZERO, world.generate_chunk(IVec2::ZERO));
+        chunks
+    }
+
+    /// The highest voxel of the column at `x`, `z`.
+    fn surface(chunks: &Chunks, x: i32, z: i32) -> IVec3 {
+        (0..100)
+            .rev()
+            .map(|y| IVec3::new(x, y, z))
+            .find(|&position| chunks.get_voxel(position).is_some())
+            .expect("no ground")
+    }
+
+    /// A column of three wooden voxels starting at `base`.
+    fn pole(base: IVec3) -> Vec<Model> {
+        let points = (0..3)
+            .map(|y| InstanceData {
+                position: Vec3::new(0.0, y as f32, 0.0),
+                color: WOOD.color,
+                is_water: 0,
+            })
+            .collect();
+        vec![Model {
+            points,
+            rotation: Quat::IDENTITY,
+            translation: base.as_vec3(),
+        }]
+    }
+
+    #[test]
+    fn undoing_an_edit_restores_the_generated_voxel() {
+        let mut chunks = chunks();
+        let mut history = History::new();
+        let position = surface(&chunks, 5, 5);
+        let generated = chunks.get_voxel(position);
+
+        history.set_voxel(&mut chunks, position, None);
+        assert_eq!(chunks.get_voxel(position), None);
+        assert!(chunks.is_edited(position));
+
+        history.undo(&mut chunks);
+        assert_eq!(chunks.get_voxel(position), generated);
+        assert!(!chunks.is_edited(position));
+
+        history.redo(&mut chunks);
+        assert_eq!(chunks.get_voxel(position), None);
+        assert!(chunks.is_edited(position));
+    }
+
+    #[test]
+    fn undoing_an_edit_restores_the_previous_edit() {
+        let mut chunks = chunks();
+        let mut history = History::new();
+        let position = IVec3::new(5, 1, 5);
+        history.set_voxel(&mut chunks, position, Some(STONE));
+        history.set_voxel(&mut chunks, position, Some(WOOD));
+
+        history.undo(&mut chunks);
+        assert_eq!(chunks.get_voxel(position), Some(STONE));
+        assert!(chunks.is_edited(position));
+    }
+
+    #[test]
+    fn strokes_are_undone_together() {
+        let mut chunks = chunks();
+        let mut history = History::new();
+        let positions = [surface(&chunks, 3, 3), surface(&chunks, 4, 3)];
+        let generated = positions.map(|p| chunks.get_voxel(p));
+
+        history.begin_stroke();
+        for position in positions {
+            history.set_voxel(&mut chunks, position, Some(WOOD));
+        }
+        history.end_stroke();
+        history.set_voxel(&mut chunks, IVec3::new(5, 1, 3), Some(WOOD));
+
+        history.undo(&mut chunks);
+        assert_eq!(positions.map(|p| chunks.get_voxel(p)), [Some(WOOD); 2]);
+        history.undo(&mut chunks);
+        assert_eq!(positions.map(|p| chunks.get_voxel(p)), generated);
+        // Nothing left to undo
+        history.undo(&mut chunks);
+        assert_eq!(positions.map(|p| chunks.get_voxel(p)), generated);
+    }
+
+    #[test]
+    fn undoing_planting_restores_the_edits_under_it() {
+        let mut chunks = chunks();
+        let mut history = History::new();
+        let base = IVec3::new(5, 60, 5);
+        let edited = base + IVec3::Y;
+        history.set_voxel(&mut chunks, edited, Some(STONE));
+
+        history.add_object(&mut chunks, IVec2::ZERO, pole(base));
+        assert_eq!(chunks.get_voxel(edited), Some(WOOD));
+        assert!(chunks.object_at(edited).is_some());
+
+        history.undo(&mut chunks);
+        assert_eq!(chunks.get_voxel(base), None);
+        assert_eq!(chunks.get_voxel(edited), Some(STONE));
+        assert!(chunks.is_edited(edited));
+
+        history.redo(&mut chunks);
+        assert_eq!(chunks.get_voxel(edited), Some(WOOD));
+        assert!(chunks.object_at(edited).is_some());
+    }
+
+    #[test]
+    fn undoing_a_removal_keeps_edits_to_the_object() {
+        let mut chunks = chunks();
+        let mut history = History::new();
+        let base = IVec3::new(5, 60, 5);
+        history.add_object(&mut chunks, IVec2::ZERO, pole(base));
+        let top = base + IVec3::Y * 2;
+        history.set_voxel(&mut chunks, top, Some(STONE));
+
+        let id = chunks.object_at(base).unwrap();
+        history.remove_object(&mut chunks, id);
+        assert_eq!(chunks.get_voxel(base), None);
+        assert_eq!(chunks.get_voxel(top), Some(STONE));
+
+        history.undo(&mut chunks);
+        assert_eq!(chunks.get_voxel(base), Some(WOOD));
+        assert_eq!(chunks.get_voxel(top), Some(STONE));
+        assert!(chunks.is_edited(top));
+        assert_eq!(chunks.object_at(base), Some(id));
+
+        // Undoing the edit of an object voxel gives it back to the object
+        history.undo(&mut chunks);
+        assert_eq!(chunks.get_voxel(top), Some(WOOD));
+        assert_eq!(chunks.object_at(top), Some(id));
+    }
+}
@@ -0,0 +1,25 @@
+//! Procedural generation of the voxel garden.
+//!
+//! Nothing in here needs a window or a GPU, so the world can be generated headless, for
+//! example to export a map or from tests. The viewer in `main.rs` is built on top of this.
+
+use glam::IVec3;
+
+pub mod chunks;
+pub mod frustum;
+pub mod map;
+pub mod mesh;
+pub mod models;
+pub mod raycast;
+pub mod utils;
+pub mod voxels;
+mod world;
+
+pub use chunks::Chunks;
+pub use models::{biomes, primitives, terrain, InstanceData, Model};
+pub use world::World;
+
+pub type Point = IVec3;
+
+/// Width and depth of a chunk, in voxels.
+pub const CHUNK_SIZE: i32 = 32;
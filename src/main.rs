@@ -1,29 +1,65 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+#[cfg(feature = "egui")]
+use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex};
-use std::{collections::HashMap, f32::consts::PI};
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, SQRT_2},
+};
 
-use glam::{IVec2, IVec3, Mat4, Quat, Vec2, Vec3, Vec4};
+use glam::{IVec2, Mat4, Quat, Vec3, Vec4};
 use miniquad::{
     conf, date, window, EventHandler, GlContext, KeyCode, PassAction, RenderingBackend as _,
 };
-use noise::Perlin;
 use ringbuffer::{AllocRingBuffer, RingBuffer as _};
 
+use voxel_garden::frustum::Frustum;
+use voxel_garden::map::export_map;
+use voxel_garden::mesh::LOD_FACTORS;
+use voxel_garden::{Chunks, World, CHUNK_SIZE};
+
 use crate::camera::{trackball_control, Movement};
-use crate::models::biomes::BiomeConfig;
-use crate::models::terrain::{generate_terrain, GenerationPositions, TerrainConfig};
-use crate::rendering::InstanceData;
-use crate::rendering::Shader;
+use crate::cli::{Command, Options};
+use crate::daylight::WorldClock;
+use crate::editing::{crosshair, crosshair_matrix, Editor};
+use crate::history::History;
+use crate::rendering::{ChunkBuffers, Fog, FogMode, RenderPath, Shader, SkyParams};
+use crate::settings::Settings;
+#[cfg(feature = "egui")]
+use crate::settings::SettingsFile;
 
 mod camera;
-mod models;
+mod cli;
+mod daylight;
+mod editing;
+mod history;
 mod rendering;
-mod utils;
-
-type Point = IVec3;
-type Terrain = HashMap<IVec2, GenerationPositions>;
-
-const CHUNK_SIZE: i32 = 32;
+mod settings;
+
+/// Colour of the water around the camera when it is under water, in daylight.
+const UNDERWATER_FOG_COLOR: Vec3 = Vec3::new(0.1, 0.35, 0.5);
+/// How far the camera can see under water, in voxels.
+const UNDERWATER_FOG_DISTANCE: f32 = 24.0;
+/// How far past the farthest chunk the far plane is, in voxels, so tall terrain and a camera
+/// high above the ground don't get clipped.
+const FAR_PLANE_MARGIN: f32 = 256.0;
+/// How many chunks closer a chunk with full-detail objects counts as when handing out the
+/// object budget, so chunks don't switch back and forth at the edge of the budget.
+const OBJECT_DETAIL_HYSTERESIS: f32 = 1.0;
+
+/// What a chunk's GPU buffers were made from: the chunk's revision, the render path, the level
+/// of detail and whether large objects are proxies.
+type BufferKey = (u64, RenderPath, usize, bool);
+
+/// Work for the terrain thread.
+enum TerrainJob {
+    /// Generate a chunk, and build what it will be drawn with on a render path at a level of
+    /// detail.
+    Generate(IVec2, RenderPath, usize),
+    /// Build the mesh of a generated chunk at a level of detail, with large objects replaced
+    /// by proxies or not.
+    Mesh(IVec2, usize, bool),
+}
 
 /// Contains state used by the application.
 struct App {
@@ -45,10 +81,14 @@ struct App {
     /// Collecst the N latest FPS values. Used for the FPS graph.
     fps_history: AllocRingBuffer<f32>,
 
-    terrain: Arc<Mutex<Terrain>>,
-    terrain_config: TerrainConfig,
-    terrain_chunk_gen_queue: mpsc::Sender<IVec2>,
+    terrain: Arc<Mutex<Chunks>>,
+    world: Arc<World>,
+    /// Chunks to generate and meshes to build, away from the main thread.
+    terrain_chunk_gen_queue: mpsc::Sender<TerrainJob>,
     terrain_chunk_waiting: HashSet<IVec2>,
+    /// The revision, level of detail and proxies of the mesh each chunk is waiting for. The
+    /// chunk's old buffers are drawn until it is built.
+    terrain_mesh_waiting: HashMap<IVec2, (u64, usize, bool)>,
 
     keys_down: HashMap<KeyCode, bool>,
     keys_just_pressed: HashSet<KeyCode>,
@@ -63,44 +103,68 @@ struct App {
     lock_mouse: bool,
     /// How many chunks to render in each direction from the camera.
     render_distance: i32,
+    /// How many chunks in each direction are drawn at full detail.
+    lod_distance: i32,
+    /// How many chunks in each direction large objects are drawn at full detail in.
+    object_detail_distance: i32,
+    /// How many large objects can be drawn at full detail. Objects further away than that are
+    /// drawn as proxies.
+    object_budget: usize,
+    /// Time of day, which moves the sun and colours the sky.
+    clock: WorldClock,
+    /// Breaking and placing voxels with the mouse.
+    editor: Editor,
+    /// Undo and redo of everything done with the editor.
+    history: History,
+    /// Whether chunks are drawn as cube instances or as meshes.
+    render_path: RenderPath,
+    /// GPU buffers of the chunks around the camera, with the revision of the chunk, the render
+    /// path and the level of detail they were made for. Empty chunks have no buffers.
+    chunk_buffers: HashMap<IVec2, (BufferKey, Option<ChunkBuffers>)>,
+    /// How many triangles were drawn for the terrain last frame.
+    drawn_triangles: usize,
+    /// How many loaded chunks within render distance were drawn last frame.
+    drawn_chunks: usize,
+    /// How many loaded chunks within render distance were outside the view last frame.
+    culled_chunks: usize,
+    /// Chunks whose large objects are drawn at full detail, see `assign_object_detail`.
+    full_detail_chunks: HashSet<IVec2>,
+    /// How many large objects are drawn at full detail.
+    full_detail_objects: usize,
+    /// How many bytes were uploaded to the GPU last frame.
+    uploaded_bytes: usize,
+    /// How many draw calls were made last frame, not counting the GUI.
+    draw_calls: usize,
+    /// How many times instances didn't fit in the instance buffer last frame.
+    split_draws: usize,
+
+    /// File that settings presets are saved to and loaded from.
+    #[cfg(feature = "egui")]
+    settings_path: PathBuf,
+    /// Name of the preset that "Save settings" and "Load settings" use.
+    #[cfg(feature = "egui")]
+    settings_preset: String,
+    /// Result of the latest save or load, shown in the File menu.
+    #[cfg(feature = "egui")]
+    settings_status: String,
 }
 
 impl App {
-    fn new() -> Self {
+    fn new(options: &Options, settings: Settings, world: World) -> Self {
         let mut ctx = GlContext::new();
         let (window_width, window_height) = window::screen_size();
 
         let shader = Shader::new(&mut ctx);
 
-        let terrain_config = TerrainConfig {
-            sample_rate: 0.004,
-            width: CHUNK_SIZE,
-            height: 20,
-            depth: CHUNK_SIZE,
-            max_height: 40.,
-            min_height: 6.,
-            noise: Perlin::new(555),
-        };
-
-        let biome_config = BiomeConfig {
-            biome_sample_rate: 0.001,
-            plant_sample_rate: 0.3,
-            noise: Perlin::new(666),
-        };
-
-        let terrain = Arc::new(Mutex::new(HashMap::new()));
+        let terrain = Arc::new(Mutex::new(Chunks::new()));
         let terrain_chunk_gen_queue = mpsc::channel();
 
+        let world = Arc::new(world);
         {
             let terrain = terrain.clone();
-            let terrain_config = terrain_config.clone();
+            let world = world.clone();
             std::thread::spawn(move || {
-                terrain_gen_thread(
-                    biome_config,
-                    terrain_config,
-                    terrain,
-                    terrain_chunk_gen_queue.1,
-                )
+                terrain_gen_thread(world, terrain, terrain_chunk_gen_queue.1)
             });
         }
 
@@ -116,39 +180,161 @@ impl App {
             prev_draw: 0.0,
             fps_history: AllocRingBuffer::new(100),
             terrain,
-            terrain_config,
+            world,
             terrain_chunk_gen_queue: terrain_chunk_gen_queue.0,
             terrain_chunk_waiting: HashSet::new(),
+            terrain_mesh_waiting: HashMap::new(),
             keys_down: HashMap::new(),
             keys_just_pressed: HashSet::new(),
             mouse_left_down: false,
             mouse_right_down: false,
             mouse_prev_pos: (0.0, 0.0),
-            movement: Movement::OnGround {
-                position: Vec3::ZERO,
-                velocity: Vec3::ZERO,
-                look_h: 0.0,
-                look_v: 0.0,
-            },
+            movement: Movement::new(options.camera, options.position),
             lock_mouse: true,
-            flying_movement_speed: 10.0,
-            on_ground_movement_speed: 40.0,
-            render_distance: 8,
+            flying_movement_speed: 0.0,
+            on_ground_movement_speed: 0.0,
+            render_distance: 0,
+            lod_distance: 0,
+            object_detail_distance: 0,
+            object_budget: 0,
+            clock: WorldClock::new(),
+            editor: Editor::new(),
+            history: History::new(),
+            render_path: RenderPath::Greedy,
+            chunk_buffers: HashMap::new(),
+            drawn_triangles: 0,
+            drawn_chunks: 0,
+            culled_chunks: 0,
+            full_detail_chunks: HashSet::new(),
+            full_detail_objects: 0,
+            uploaded_bytes: 0,
+            draw_calls: 0,
+            split_draws: 0,
+            #[cfg(feature = "egui")]
+            settings_path: options.settings.clone(),
+            #[cfg(feature = "egui")]
+            settings_preset: options.preset.clone(),
+            #[cfg(feature = "egui")]
+            settings_status: String::new(),
         };
+        app.apply_settings(settings);
         // Make sure aspect_ratio and fov_y_radians are correct at the first draw
         app.resize_event(window_width, window_height);
         app
     }
 
+    #[cfg(feature = "egui")]
+    fn settings(&self) -> Settings {
+        Settings {
+            render_distance: self.render_distance,
+            lod_distance: self.lod_distance,
+            object_detail_distance: self.object_detail_distance,
+            object_budget: self.object_budget,
+            render_path: self.render_path,
+            flying_movement_speed: self.flying_movement_speed,
+            on_ground_movement_speed: self.on_ground_movement_speed,
+            day_length: self.clock.day_length,
+            time_speed: self.clock.speed,
+            shader: self.shader.settings,
+        }
+    }
+
+    fn apply_settings(&mut self, settings: Settings) {
+        self.render_distance = settings.render_distance;
+        self.lod_distance = settings.lod_distance;
+        self.object_detail_distance = settings.object_detail_distance;
+        self.object_budget = settings.object_budget;
+        self.render_path = settings.render_path;
+        self.flying_movement_speed = settings.flying_movement_speed;
+        self.on_ground_movement_speed = settings.on_ground_movement_speed;
+        self.clock.day_length = settings.day_length;
+        self.clock.speed = settings.time_speed;
+        self.shader.settings = settings.shader;
+    }
+
+    /// Saves the current settings as the preset `settings_preset`, keeping all other presets
+    /// in the file.
+    #[cfg(feature = "egui")]
+    fn save_settings(&mut self) {
+        let result = SettingsFile::load(&self.settings_path).and_then(|mut file| {
+            file.insert(&self.settings_preset, self.settings());
+            file.save(&self.settings_path)
+        });
+        self.settings_status = match result {
+            Ok(()) => format!("Saved preset {:?}", self.settings_preset),
+            Err(e) => e,
+        };
+    }
+
+    #[cfg(feature = "egui")]
+    fn load_settings(&mut self) {
+        let result = SettingsFile::load(&self.settings_path).and_then(|file| {
+            file.get(&self.settings_preset).ok_or(format!(
+                "No preset named {:?} in {}",
+                self.settings_preset,
+                self.settings_path.display()
+            ))
+        });
+        self.settings_status = match result {
+            Ok(settings) => {
+                self.apply_settings(settings);
+                format!("Loaded preset {:?}", self.settings_preset)
+            }
+            Err(e) => e,
+        };
+    }
+
     #[cfg(feature = "egui")]
     fn egui_ui(&mut self) {
         use egui::TopBottomPanel;
         use egui_plot::{Line, Plot, PlotPoints};
 
+        enum SettingsAction {
+            Save,
+            Load,
+            Reset,
+        }
+        // Saving and loading needs all of self, which is borrowed while building the UI
+        let mut settings_action = None;
+
         self.egui_mq.run(&mut self.ctx, |_ctx, egui_ctx| {
             TopBottomPanel::top("top bar").show(egui_ctx, |ui| {
                 ui.horizontal(|ui| {
                     ui.menu_button("File", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Preset");
+                            ui.text_edit_singleline(&mut self.settings_preset);
+                        });
+                        ui.menu_button("Saved presets", |ui| {
+                            let presets = SettingsFile::load(&self.settings_path)
+                                .map(|file| {
+                                    file.preset_names().map(str::to_string).collect::<Vec<_>>()
+                                })
+                                .unwrap_or_default();
+                            if presets.is_empty() {
+                                ui.label("No saved presets");
+                            }
+                            for preset in presets {
+                                if ui.button(&preset).clicked() {
+                                    self.settings_preset = preset;
+                                    settings_action = Some(SettingsAction::Load);
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                        if ui.button("Save settings").clicked() {
+                            settings_action = Some(SettingsAction::Save);
+                        }
+                        if ui.button("Load settings").clicked() {
+                            settings_action = Some(SettingsAction::Load);
+                        }
+                        if ui.button("Reset to defaults").clicked() {
+                            settings_action = Some(SettingsAction::Reset);
+                        }
+                        if !self.settings_status.is_empty() {
+                            ui.label(&self.settings_status);
+                        }
+                        ui.separator();
                         if ui.button("Quit").clicked() {
                             unimplemented!("this is ironic");
                         }
@@ -175,7 +361,26 @@ impl App {
                 egui::Grid::new("sliders").num_columns(2).show(ui, |ui| {
                     ui.label("render distance");
                     ui.add(
-                        egui::Slider::new(&mut self.render_distance, 1..=32).clamp_to_range(true),
+                        egui::Slider::new(&mut self.render_distance, 1..=64).clamp_to_range(true),
+                    );
+                    ui.end_row();
+
+                    ui.label("full detail distance");
+                    ui.add(egui::Slider::new(&mut self.lod_distance, 1..=64).clamp_to_range(true));
+                    ui.end_row();
+
+                    ui.label("object detail distance");
+                    ui.add(
+                        egui::Slider::new(&mut self.object_detail_distance, 0..=64)
+                            .clamp_to_range(true),
+                    );
+                    ui.end_row();
+
+                    ui.label("object budget");
+                    ui.add(
+                        egui::Slider::new(&mut self.object_budget, 0..=10_000)
+                            .clamp_to_range(true)
+                            .logarithmic(true),
                     );
                     ui.end_row();
 
@@ -193,10 +398,55 @@ impl App {
                     );
                     ui.end_row();
 
+                    ui.label("render path");
+                    egui::ComboBox::from_id_source("render path")
+                        .selected_text(self.render_path.to_string())
+                        .show_ui(ui, |ui| {
+                            for path in [RenderPath::Greedy, RenderPath::Instanced] {
+                                ui.selectable_value(&mut self.render_path, path, path.to_string());
+                            }
+                        });
+                    ui.end_row();
+
+                    self.clock.egui_rows(ui);
+
+                    self.editor.egui_rows(ui);
+
                     self.shader.egui_uniform_slider_rows(ui);
                 });
             });
 
+            egui::Window::new("History").show(egui_ctx, |ui| {
+                let mut terrain = self.terrain.lock().unwrap();
+                ui.horizontal(|ui| {
+                    if ui.button("Undo").clicked() {
+                        self.history.undo(&mut terrain);
+                    }
+                    if ui.button("Redo").clicked() {
+                        self.history.redo(&mut terrain);
+                    }
+                });
+                let (entries, done) = self.history.entries();
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        // Selecting an entry undoes or redoes everything after it
+                        if ui.selectable_label(done == 0, "(start)").clicked() {
+                            self.history.go_to(&mut terrain, 0);
+                        }
+                        for (i, entry) in entries.iter().enumerate() {
+                            let label = if i < done {
+                                egui::RichText::new(entry)
+                            } else {
+                                egui::RichText::new(entry).weak()
+                            };
+                            if ui.selectable_label(i + 1 == done, label).clicked() {
+                                self.history.go_to(&mut terrain, i + 1);
+                            }
+                        }
+                    });
+            });
+
             egui::Window::new("Performance").show(egui_ctx, |ui| {
                 ui.label(format!(
                     "Average FPS: {:.0}",
@@ -208,6 +458,28 @@ impl App {
                     self.terrain_chunk_waiting.len()
                 ));
 
+                ui.label(format!(
+                    "Chunks drawn: {}, culled: {}",
+                    self.drawn_chunks, self.culled_chunks
+                ));
+
+                ui.label(format!(
+                    "Objects at full detail: {} (budget {})",
+                    self.full_detail_objects, self.object_budget
+                ));
+
+                ui.label(format!("Terrain triangles: {}", self.drawn_triangles));
+
+                // Objects are baked into their chunk, so this is about one per chunk
+                ui.label(format!("Draw calls: {}", self.draw_calls));
+
+                ui.label(format!("Draws split into batches: {}", self.split_draws));
+
+                ui.label(format!(
+                    "Uploaded last frame: {:.1} KiB",
+                    self.uploaded_bytes as f32 / 1024.0
+                ));
+
                 let fps_points: PlotPoints = self
                     .fps_history
                     .iter()
@@ -228,39 +500,97 @@ impl App {
         });
 
         self.egui_mq.draw(&mut self.ctx);
+
+        match settings_action {
+            Some(SettingsAction::Save) => self.save_settings(),
+            Some(SettingsAction::Load) => self.load_settings(),
+            Some(SettingsAction::Reset) => {
+                self.apply_settings(Settings::default());
+                self.settings_status = "Reset to defaults".to_string();
+            }
+            None => {}
+        }
+    }
+
+    /// The camera matrix, including the trackball rotation.
+    fn camera(&self) -> Mat4 {
+        // FIXME
+        self.movement.camera_matrix()
+            * match self.movement {
+                Movement::Trackball { matrix, .. } => matrix,
+                _ => Mat4::IDENTITY,
+            }
+    }
+
+    /// Whether clicking should edit the world. The trackball camera uses the mouse for
+    /// rotating, and clicks on the GUI shouldn't go through to the world.
+    fn can_edit(&self) -> bool {
+        #[cfg(feature = "egui")]
+        if self.egui_mq.egui_ctx().wants_pointer_input() {
+            return false;
+        }
+        self.lock_mouse && !matches!(self.movement, Movement::Trackball { .. })
     }
 
-    fn generate_chunk(
-        biome_config: &BiomeConfig,
-        terrain_config: &TerrainConfig,
-        chunk: IVec2,
-    ) -> GenerationPositions {
-        generate_terrain(
-            chunk.x * CHUNK_SIZE,
-            chunk.y * CHUNK_SIZE,
-            terrain_config,
-            biome_config,
-        )
+    /// Whether keys are going to a text field in the GUI, so they shouldn't be used as
+    /// shortcuts.
+    fn typing(&self) -> bool {
+        #[cfg(feature = "egui")]
+        if self.egui_mq.egui_ctx().wants_keyboard_input() {
+            return true;
+        }
+        false
     }
 
-    fn draw_chunk_and_around(
+    /// Level of detail to draw a chunk `d_chunk` chunks from the camera with. 0 is full detail
+    /// and every level after that is for twice the distance.
+    fn lod(&self, d_chunk: IVec2) -> usize {
+        let distance = d_chunk.abs().max_element();
+        let mut lod = 0;
+        let mut limit = self.lod_distance;
+        while distance > limit && lod < LOD_FACTORS.len() {
+            lod += 1;
+            limit *= 2;
+        }
+        lod
+    }
+
+    /// Distance to the far plane, far enough to see the corners of the farthest chunks in
+    /// render distance wherever the camera is in its chunk.
+    fn far_plane(&self) -> f32 {
+        ((self.render_distance + 1) * CHUNK_SIZE) as f32 * SQRT_2 + FAR_PLANE_MARGIN
+    }
+
+    /// Requests the chunks around the camera that haven't been generated yet and uploads the
+    /// ones that will be drawn. Returns the chunks in view, nearest first, and the chunks that
+    /// can cast shadows into the shadow map.
+    fn prepare_chunks(
         &mut self,
         projection: Mat4,
         camera: Mat4,
         camera_position: IVec2,
-        camera_look_h: Option<f32>,
-    ) {
+    ) -> (Vec<IVec2>, Vec<IVec2>) {
         // Which chunk is the camera located in?
         let camera_chunk = IVec2::new(
-            camera_position.x / CHUNK_SIZE,
-            camera_position.y / CHUNK_SIZE,
+            camera_position.x.div_euclid(CHUNK_SIZE),
+            camera_position.y.div_euclid(CHUNK_SIZE),
         );
+        self.drawn_chunks = 0;
+        self.culled_chunks = 0;
+        let frustum = Frustum::from_matrix(projection * camera);
+        let light_frustum = Frustum::from_matrix(self.shader.light_matrix());
+        let mut visible_chunks = Vec::new();
+        let mut shadow_casters = Vec::new();
         for dy in -self.render_distance..=self.render_distance {
             for dx in -self.render_distance..=self.render_distance {
                 let terrain = self.terrain.lock().unwrap();
                 let d_chunk = IVec2::new(dx, dy);
                 let chunk = camera_chunk + d_chunk;
 
+                if !self.world.chunk_in_bounds(chunk) {
+                    continue;
+                }
+
                 // remove generated chunks from queue
                 if self.terrain_chunk_waiting.contains(&chunk) && terrain.contains_key(&chunk) {
                     self.terrain_chunk_waiting.remove(&chunk);
@@ -270,45 +600,193 @@ impl App {
                 if !terrain.contains_key(&chunk) {
                     // only send the request if we haven't already sent it
                     if !self.terrain_chunk_waiting.contains(&chunk) {
-                        self.terrain_chunk_gen_queue.send(chunk).unwrap();
+                        self.terrain_chunk_gen_queue
+                            .send(TerrainJob::Generate(
+                                chunk,
+                                self.render_path,
+                                self.lod(d_chunk),
+                            ))
+                            .unwrap();
                         self.terrain_chunk_waiting.insert(chunk);
                     }
                     continue;
                 }
 
-                if let Some(camera_look_h) = camera_look_h {
-                    let (vs, vc) = camera_look_h.sin_cos();
-                    let look_v = Vec2::new(vs, vc).normalize();
-                    if look_v.dot(d_chunk.as_vec2()) < 0.0 {
-                        continue;
-                    }
+                let Some(bounds) = terrain.get(&chunk).unwrap().bounds else {
+                    continue;
+                };
+                // Chunks behind the camera can still cast shadows into view
+                if self.shader.settings.shadows && light_frustum.intersects(&bounds) {
+                    shadow_casters.push(chunk);
+                }
+                if frustum.intersects(&bounds) {
+                    visible_chunks.push(d_chunk);
+                } else {
+                    self.culled_chunks += 1;
                 }
-                let chunk_data = terrain.get(&chunk).unwrap();
+            }
+        }
 
-                // Draw ground
-                self.shader.draw_voxels(
-                    &mut self.ctx,
-                    &chunk_data.ground,
-                    projection,
-                    camera,
-                    camera,
-                );
+        self.assign_object_detail(camera_chunk, &visible_chunks);
+        // Nearest first, so fewer hidden fragments are shaded
+        visible_chunks.sort_by_key(|d_chunk| d_chunk.length_squared());
+        let visible_chunks: Vec<IVec2> = visible_chunks
+            .into_iter()
+            .map(|d_chunk| {
+                self.upload_chunk(camera_chunk, d_chunk);
+                camera_chunk + d_chunk
+            })
+            .collect();
+        self.drawn_chunks = visible_chunks.len();
+        // Casters out of view haven't been uploaded above
+        for &chunk in &shadow_casters {
+            if !self.chunk_buffers.contains_key(&chunk) {
+                self.upload_chunk(camera_chunk, chunk - camera_chunk);
+            }
+        }
 
-                // First collect all models (in the current chunk) in an iterator
-                let models = chunk_data.objects.iter().flatten();
-
-                // Then draw each model one at a time
-                for model in models {
-                    self.shader.draw_voxels(
-                        &mut self.ctx,
-                        &model.points,
-                        projection,
-                        camera * Mat4::from_rotation_translation(model.rotation, model.translation),
-                        camera,
-                    );
+        // Free the buffers of chunks that are out of render distance
+        let render_distance = self.render_distance;
+        let far_away: Vec<IVec2> = self
+            .chunk_buffers
+            .keys()
+            .filter(|chunk| (**chunk - camera_chunk).abs().max_element() > render_distance)
+            .copied()
+            .collect();
+        for chunk in far_away {
+            if let Some((_, Some(buffers))) = self.chunk_buffers.remove(&chunk) {
+                buffers.delete(&mut self.ctx);
+            }
+        }
+        self.terrain_mesh_waiting
+            .retain(|chunk, _| (*chunk - camera_chunk).abs().max_element() <= render_distance);
+
+        (visible_chunks, shadow_casters)
+    }
+
+    /// Chooses the chunks whose large objects are drawn at full detail: the nearest of
+    /// `visible_chunks` (relative to the camera's chunk) whose objects fit in the object budget,
+    /// within the object detail distance. Only objects with proxies count, and chunks out of
+    /// view don't take any of the budget. Chunks that already have full detail count as
+    /// `OBJECT_DETAIL_HYSTERESIS` chunks closer, so they keep it until a chunk that is clearly
+    /// closer needs the budget.
+    fn assign_object_detail(&mut self, camera_chunk: IVec2, visible_chunks: &[IVec2]) {
+        let terrain = self.terrain.lock().unwrap();
+        let distance = self.object_detail_distance.min(self.render_distance);
+        let mut candidates = Vec::new();
+        for &d_chunk in visible_chunks {
+            let chunk = camera_chunk + d_chunk;
+            // Coarser levels of detail are coarse enough already
+            if d_chunk.abs().max_element() > distance || self.lod(d_chunk) > 0 {
+                continue;
+            }
+            let Some(chunk_data) = terrain.get(&chunk) else {
+                continue;
+            };
+            let objects = chunk_data.objects.iter().filter(|o| o.has_proxy()).count();
+            let mut rank = d_chunk.as_vec2().length();
+            if self.full_detail_chunks.contains(&chunk) {
+                rank -= OBJECT_DETAIL_HYSTERESIS;
+            }
+            candidates.push((rank, chunk, objects));
+        }
+        // Ties are broken by position, so the choice doesn't depend on the order of the loop
+        candidates.sort_by(|(a, a_chunk, _), (b, b_chunk, _)| {
+            a.total_cmp(b)
+                .then_with(|| a_chunk.to_array().cmp(&b_chunk.to_array()))
+        });
+
+        self.full_detail_chunks.clear();
+        self.full_detail_objects = 0;
+        for (_, chunk, objects) in candidates {
+            if self.full_detail_objects + objects > self.object_budget {
+                break;
+            }
+            self.full_detail_objects += objects;
+            self.full_detail_chunks.insert(chunk);
+        }
+    }
+
+    /// Uploads the chunk `d_chunk` chunks from the camera at the level of detail it should be
+    /// drawn with, unless it is uploaded already.
+    fn upload_chunk(&mut self, camera_chunk: IVec2, d_chunk: IVec2) {
+        let chunk = camera_chunk + d_chunk;
+        let terrain = self.terrain.lock().unwrap();
+        let chunk_data = terrain.get(&chunk).unwrap();
+
+        let lod = match self.render_path {
+            RenderPath::Instanced => 0,
+            RenderPath::Greedy => self.lod(d_chunk),
+        };
+        // Large objects in chunks that didn't get any of the object budget are drawn as
+        // proxies. Coarser levels of detail are coarse enough already.
+        let proxies = self.render_path == RenderPath::Greedy
+            && lod == 0
+            && !self.full_detail_chunks.contains(&chunk);
+
+        // Upload the chunk again if it changed since last time. Objects are part of the chunk's
+        // voxels, so the whole chunk is one draw
+        let wanted = (chunk_data.revision, self.render_path, lod, proxies);
+        let uploaded = self.chunk_buffers.get(&chunk).map(|(key, _)| *key);
+        if uploaded == Some(wanted) {
+            return;
+        }
+        // Meshes are built on the terrain thread, and the old buffers are drawn until it's done
+        if self.render_path == RenderPath::Greedy && chunk_data.built_mesh(lod, proxies).is_none() {
+            let waiting = (chunk_data.revision, lod, proxies);
+            if self.terrain_mesh_waiting.get(&chunk) != Some(&waiting) {
+                self.terrain_chunk_gen_queue
+                    .send(TerrainJob::Mesh(chunk, lod, proxies))
+                    .unwrap();
+                self.terrain_mesh_waiting.insert(chunk, waiting);
+            }
+            // Buffers made for the other render path can't be drawn with this one
+            if uploaded.is_some_and(|(_, path, _, _)| path != self.render_path) {
+                if let Some((_, Some(old))) = self.chunk_buffers.remove(&chunk) {
+                    old.delete(&mut self.ctx);
                 }
             }
+            return;
+        }
+        let buffers = self.shader.upload_chunk(
+            &mut self.ctx,
+            self.render_path,
+            &terrain,
+            chunk,
+            lod,
+            proxies,
+        );
+        let old = self.chunk_buffers.insert(chunk, (wanted, buffers));
+        if let Some((_, Some(old))) = old {
+            old.delete(&mut self.ctx);
+        }
+    }
+
+    /// Draws the chunks from the sun's point of view into the shadow map.
+    fn draw_shadows(&mut self, chunks: &[IVec2]) {
+        self.shader.begin_shadow_pass(&mut self.ctx);
+        for chunk in chunks {
+            if let Some((_, Some(buffers))) = self.chunk_buffers.get(chunk) {
+                self.shader.draw_chunk_shadow(&mut self.ctx, buffers);
+            }
         }
+        self.ctx.end_render_pass();
+    }
+
+    fn draw_chunks(&mut self, chunks: &[IVec2], projection: Mat4, camera: Mat4) {
+        self.shader
+            .prepare_chunk_draw(&mut self.ctx, self.render_path);
+        self.drawn_triangles = 0;
+        for chunk in chunks {
+            if let Some((_, Some(buffers))) = self.chunk_buffers.get(chunk) {
+                self.shader
+                    .draw_chunk(&mut self.ctx, buffers, projection, camera, camera);
+                self.drawn_triangles += buffers.triangles();
+            }
+        }
+
+        // Everything else is drawn as instances
+        self.shader.prepare_draw(&mut self.ctx);
     }
 }
 
@@ -318,10 +796,34 @@ impl EventHandler for App {
         let delta = (now - self.prev_update) as f32;
         self.prev_update = now;
 
+        self.clock.advance(delta);
+
         if self.keys_just_pressed.contains(&KeyCode::F1) {
             self.lock_mouse ^= true;
         }
 
+        let typing = self.typing();
+        if !typing && self.keys_just_pressed.contains(&KeyCode::T) {
+            self.editor.next_tool();
+        }
+
+        // Ctrl+Z undoes, Ctrl+Y and Ctrl+Shift+Z redo
+        let key_down = |key| self.keys_down.get(&key).copied().unwrap_or(false);
+        if !typing && (key_down(KeyCode::LeftControl) || key_down(KeyCode::RightControl)) {
+            let shift = key_down(KeyCode::LeftShift) || key_down(KeyCode::RightShift);
+            let mut terrain = self.terrain.lock().unwrap();
+            if self.keys_just_pressed.contains(&KeyCode::Y)
+                || (shift && self.keys_just_pressed.contains(&KeyCode::Z))
+            {
+                self.history.redo(&mut terrain);
+            } else if self.keys_just_pressed.contains(&KeyCode::Z) {
+                self.history.undo(&mut terrain);
+            }
+        }
+
+        // Edited chunks get new instance data once per frame, however many voxels changed
+        self.terrain.lock().unwrap().remesh_dirty();
+
         // Apply camera movement
         match &mut self.movement {
             // Trackball camera cannot move
@@ -363,6 +865,7 @@ impl EventHandler for App {
                         * delta
                         * self.flying_movement_speed;
                 }
+                *position = self.world.terrain_config.clamp_position(*position);
             }
             Movement::OnGround {
                 position,
@@ -389,8 +892,9 @@ impl EventHandler for App {
                         * delta
                         * self.on_ground_movement_speed;
                 }
+                *position = self.world.terrain_config.clamp_position(*position);
 
-                let height_at_p = self.terrain_config.sample(position.x, position.z) + 20.0;
+                let height_at_p = self.world.height_at(position.x, position.z) + 20.0;
 
                 let mut on_ground = position.y <= height_at_p;
                 if on_ground
@@ -414,6 +918,17 @@ impl EventHandler for App {
             }
         }
 
+        // Find what the crosshair points at, from the camera's position in world space
+        if self.can_edit() {
+            let inverse_camera = self.camera().inverse();
+            let origin = inverse_camera.transform_point3(Vec3::ZERO);
+            let direction = inverse_camera.transform_vector3(Vec3::NEG_Z);
+            self.editor
+                .update_target(&self.terrain.lock().unwrap(), origin, direction);
+        } else {
+            self.editor.target = None;
+        }
+
         self.keys_just_pressed.clear();
     }
 
@@ -428,24 +943,9 @@ impl EventHandler for App {
         self.prev_draw = now;
         self.fps_history.push(1.0 / draw_delta);
 
-        self.shader.prepare_draw(&mut self.ctx);
-
-        // sky
-        self.ctx.begin_default_pass(PassAction::clear_color(
-            0x87 as f32 / 255.0,
-            0xCE as f32 / 255.0,
-            0xEB as f32 / 255.0,
-            1.0,
-        ));
-
         let projection =
-            Mat4::perspective_rh_gl(self.fov_y_radians, self.aspect_ratio, 0.1, 1000.0);
-        // FIXME
-        let camera = self.movement.camera_matrix()
-            * match self.movement {
-                Movement::Trackball { matrix, .. } => matrix,
-                _ => Mat4::IDENTITY,
-            };
+            Mat4::perspective_rh_gl(self.fov_y_radians, self.aspect_ratio, 0.1, self.far_plane());
+        let camera = self.camera();
 
         let camera_position_2d = match self.movement {
             Movement::Trackball { .. } => IVec2::new(0, 0),
@@ -453,14 +953,105 @@ impl EventHandler for App {
                 IVec2::new(position.x.trunc() as i32, position.z.trunc() as i32)
             }
         };
-        let camera_look_h = match self.movement {
-            Movement::Trackball { .. } => None,
-            Movement::Flying { look_h, .. } | Movement::OnGround { look_h, .. } => Some(look_h),
+        let daylight = self.clock.daylight(
+            self.shader.settings.sun_color,
+            self.shader.settings.ambient_light_color,
+        );
+        self.shader.set_light(
+            daylight.light_direction,
+            daylight.light_color,
+            daylight.ambient_color,
+        );
+
+        // The shadow map follows the camera, and needs to be in place before finding out which
+        // chunks cast shadows into it
+        let eye = camera.inverse().transform_point3(Vec3::ZERO);
+        self.shader.update_shadow_map(&mut self.ctx, eye);
+        let (visible_chunks, shadow_casters) =
+            self.prepare_chunks(projection, camera, camera_position_2d);
+        if self.shader.settings.shadows {
+            self.draw_shadows(&shadow_casters);
+        }
+
+        // Water is a thin layer with air under it, so anywhere below its surface is under water
+        let underwater = self
+            .terrain
+            .lock()
+            .unwrap()
+            .water_surface(eye.x.round() as i32, eye.z.round() as i32)
+            .is_some_and(|surface| eye.y < surface);
+        let fog = if underwater {
+            // Darker at night, like everything else
+            Some(Fog {
+                color: UNDERWATER_FOG_COLOR * daylight.ambient_color.truncate(),
+                start: 0.0,
+                end: UNDERWATER_FOG_DISTANCE,
+                exponential: true,
+            })
+        } else {
+            // Thickest where chunks stop being loaded, hiding them popping in and out
+            let end = (self.render_distance * CHUNK_SIZE) as f32;
+            let settings = self.shader.settings;
+            (settings.fog != FogMode::Off).then_some(Fog {
+                color: daylight.sky_color,
+                start: end * settings.fog_start,
+                end,
+                exponential: settings.fog == FogMode::Exponential,
+            })
         };
+        self.shader.set_fog(fog);
+
+        if let Some(fog) = fog.filter(|_| underwater) {
+            // Nothing can be seen of the sky under water
+            let color = fog.color;
+            self.ctx
+                .begin_default_pass(PassAction::clear_color(color.x, color.y, color.z, 1.0));
+        } else {
+            // The sky covers the whole screen, so only depth needs clearing
+            self.ctx.begin_default_pass(PassAction::Clear {
+                color: None,
+                depth: Some(1.0),
+                stencil: None,
+            });
+            let sky = SkyParams {
+                zenith_color: daylight.zenith_color,
+                horizon_color: daylight.sky_color,
+                sun_direction: self.clock.sun_direction(),
+                moon_direction: self.clock.moon_direction(),
+                star_rotation: Mat4::from_rotation_z(-self.clock.sky_angle()),
+                star_visibility: daylight.star_visibility,
+            };
+            self.shader
+                .draw_sky(&mut self.ctx, projection, camera, &sky);
+        }
+        self.draw_chunks(&visible_chunks, projection, camera);
 
-        self.draw_chunk_and_around(projection, camera, camera_position_2d, camera_look_h);
+        if self.can_edit() {
+            let highlight = self.editor.highlight(&self.terrain.lock().unwrap());
+            if let Some((instance, model)) = highlight {
+                self.shader.draw_voxels(
+                    &mut self.ctx,
+                    &[instance],
+                    projection,
+                    camera * model,
+                    camera,
+                );
+            }
+            // The crosshair is on the screen, not in the world
+            self.shader.set_fog(None);
+            self.shader.draw_voxels(
+                &mut self.ctx,
+                &crosshair(),
+                projection,
+                crosshair_matrix(),
+                Mat4::IDENTITY,
+            );
+        }
 
         self.ctx.end_render_pass();
+        self.uploaded_bytes = self.shader.take_uploaded_bytes();
+        self.draw_calls = self.shader.take_draw_calls();
+        self.split_draws = self.shader.take_split_draws();
 
         #[cfg(feature = "egui")]
         self.egui_ui();
@@ -528,6 +1119,21 @@ impl EventHandler for App {
             miniquad::MouseButton::Right => self.mouse_right_down = true,
             _ => {}
         }
+
+        if self.can_edit() {
+            let mut terrain = self.terrain.lock().unwrap();
+            // Everything a click does is undone together
+            self.history.begin_stroke();
+            match mb {
+                miniquad::MouseButton::Left => {
+                    self.editor.left_click(&mut terrain, &mut self.history)
+                }
+                miniquad::MouseButton::Right => {
+                    self.editor.right_click(&mut terrain, &mut self.history)
+                }
+                _ => {}
+            }
+        }
     }
 
     fn mouse_button_up_event(&mut self, mb: miniquad::MouseButton, x: f32, y: f32) {
@@ -539,6 +1145,7 @@ impl EventHandler for App {
             miniquad::MouseButton::Right => self.mouse_right_down = false,
             _ => {}
         }
+        self.history.end_stroke();
     }
 
     fn char_event(&mut self, character: char, _keymods: miniquad::KeyMods, _repeat: bool) {
@@ -568,26 +1175,97 @@ impl EventHandler for App {
 }
 
 fn terrain_gen_thread(
-    biome_config: BiomeConfig,
-    terrain_config: TerrainConfig,
-    terrain: Arc<Mutex<Terrain>>,
-    gen_queue: mpsc::Receiver<IVec2>,
+    world: Arc<World>,
+    terrain: Arc<Mutex<Chunks>>,
+    gen_queue: mpsc::Receiver<TerrainJob>,
 ) {
-    for chunk in gen_queue.iter() {
-        if terrain.lock().unwrap().contains_key(&chunk) {
-            continue;
+    let mut jobs = VecDeque::new();
+    loop {
+        jobs.extend(gen_queue.try_iter());
+        if jobs.is_empty() {
+            match gen_queue.recv() {
+                Ok(job) => jobs.push_back(job),
+                Err(_) => return,
+            }
+        }
+        // Chunks waiting for a mesh are on screen already, so they go before new chunks
+        let next = jobs
+            .iter()
+            .position(|job| matches!(job, TerrainJob::Mesh(..)))
+            .unwrap_or(0);
+        match jobs.remove(next).unwrap() {
+            TerrainJob::Generate(chunk, path, lod) => {
+                if terrain.lock().unwrap().contains_key(&chunk) {
+                    continue;
+                }
+                let data = world.generate_chunk(chunk);
+                // Build what the chunk is drawn with here rather than on the main thread
+                match path {
+                    RenderPath::Instanced => {
+                        data.instances();
+                    }
+                    RenderPath::Greedy => {
+                        data.mesh(lod);
+                    }
+                }
+                terrain.lock().unwrap().insert(chunk, data);
+            }
+            TerrainJob::Mesh(chunk, lod, proxies) => {
+                // Build from a copy, so the main thread can keep using the chunks meanwhile
+                let Some(source) = terrain.lock().unwrap().mesh_source(chunk, lod, proxies) else {
+                    continue;
+                };
+                let mesh = source.build();
+                terrain.lock().unwrap().insert_mesh(&source, mesh);
+            }
         }
-        let data = App::generate_chunk(&biome_config, &terrain_config, chunk);
-        terrain.lock().unwrap().insert(chunk, data);
     }
 }
 
 fn main() {
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Help) => {
+            print!("{}", cli::help());
+            return;
+        }
+        Ok(Command::Map(options, map)) => {
+            let result = options.world().and_then(|world| {
+                export_map(&map, &world.terrain_config, &world.biome_config)
+                    .map_err(|e| format!("couldn't write {}: {e}", map.output.display()))
+            });
+            if let Err(e) = result {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(Command::Viewer(options)) => options,
+        Err(e) => {
+            eprintln!("error: {e}");
+            eprintln!("Run with --help to see all options.");
+            std::process::exit(1);
+        }
+    };
+    let world = match options.world() {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let settings = match options.settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+
     let conf = conf::Conf {
         window_title: "voxel garden".to_string(),
-        window_width: 800,
-        window_height: 800,
+        window_width: options.window_size.x,
+        window_height: options.window_size.y,
         ..conf::Conf::default()
     };
-    miniquad::start(conf, move || Box::new(App::new()));
+    miniquad::start(conf, move || Box::new(App::new(&options, settings, world)));
 }
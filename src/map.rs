@@ -0,0 +1,284 @@
+//! Headless rendering of a top-down map of the generated world, without opening a window.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glam::{IVec2, Vec3, Vec4};
+
+use crate::models::biomes::{Biome, BiomeConfig};
+use crate::models::terrain::{generate_terrain, SpawnType, TerrainConfig};
+use crate::utils::WATER_BLUE;
+use crate::CHUNK_SIZE;
+
+/// What the map shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapView {
+    /// Greyscale shading by terrain height.
+    Height,
+    /// The colour of the ground voxels, as seen from above.
+    Ground,
+    /// One flat colour per biome.
+    Biome,
+    /// Water shaded by depth, land dimmed.
+    Water,
+    /// Height shading with object spawn points marked by spawn type.
+    Objects,
+}
+
+impl FromStr for MapView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "height" => Ok(MapView::Height),
+            "ground" => Ok(MapView::Ground),
+            "biome" => Ok(MapView::Biome),
+            "water" => Ok(MapView::Water),
+            "objects" => Ok(MapView::Objects),
+            _ => Err(format!(
+                "invalid map view {s:?}, expected one of height, ground, biome, water or objects"
+            )),
+        }
+    }
+}
+
+/// Settings for a map export.
+pub struct MapExport {
+    pub output: PathBuf,
+    pub view: MapView,
+    /// World coordinate of the top left corner of the map.
+    pub origin: IVec2,
+    /// Size of the map in voxels. Every voxel is one pixel.
+    pub size: IVec2,
+}
+
+impl MapExport {
+    pub fn new(output: PathBuf) -> Self {
+        MapExport {
+            output,
+            view: MapView::Ground,
+            origin: IVec2::new(-256, -256),
+            size: IVec2::new(512, 512),
+        }
+    }
+}
+
+/// The topmost voxel of a column.
+#[derive(Clone, Copy)]
+struct Column {
+    height: f32,
+    color: Vec4,
+    is_water: bool,
+}
+
+/// Number of pixels in the map. Fails if the map is empty, has more pixels than can be
+/// addressed, or reaches past the largest world coordinate.
+fn pixel_count(export: &MapExport) -> io::Result<usize> {
+    let MapExport { origin, size, .. } = *export;
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "map is too large");
+    if size.cmplt(IVec2::ONE).any() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "map size must be positive",
+        ));
+    }
+    origin
+        .x
+        .checked_add(size.x)
+        .zip(origin.y.checked_add(size.y))
+        .ok_or_else(too_large)?;
+    (size.x as usize)
+        .checked_mul(size.y as usize)
+        .ok_or_else(too_large)
+}
+
+/// Renders the map to an RGB image, one pixel per voxel.
+pub fn render_map(
+    export: &MapExport,
+    terrain_config: &TerrainConfig,
+    biome_config: &BiomeConfig,
+) -> io::Result<Vec<[u8; 3]>> {
+    let pixel_count = pixel_count(export)?;
+    let MapExport { origin, size, .. } = *export;
+    let width = size.x as usize;
+    let index = |x: i32, z: i32| -> Option<usize> {
+        let x = usize::try_from(x as i64 - origin.x as i64).ok()?;
+        let z = usize::try_from(z as i64 - origin.y as i64).ok()?;
+        (x < width && z < size.y as usize).then(|| z * width + x)
+    };
+
+    let mut columns: Vec<Option<Column>> = vec![None; pixel_count];
+    let mut markers = Vec::new();
+
+    let first_chunk = IVec2::new(
+        origin.x.div_euclid(CHUNK_SIZE),
+        origin.y.div_euclid(CHUNK_SIZE),
+    );
+    let last_chunk = IVec2::new(
+        (origin.x + size.x - 1).div_euclid(CHUNK_SIZE),
+        (origin.y + size.y - 1).div_euclid(CHUNK_SIZE),
+    );
+    for chunk_z in first_chunk.y..=last_chunk.y {
+        for chunk_x in first_chunk.x..=last_chunk.x {
+            if !terrain_config.chunk_in_bounds(IVec2::new(chunk_x, chunk_z)) {
+                continue;
+            }
+            let chunk = generate_terrain(
+                chunk_x * CHUNK_SIZE,
+                chunk_z * CHUNK_SIZE,
+                terrain_config,
+                biome_config,
+            );
+            // The topmost voxel of every column, including objects, like looking down from above
+            for voxel in chunk.instances() {
+                let Some(i) = index(voxel.position.x as i32, voxel.position.z as i32) else {
+                    continue;
+                };
+                if columns[i].is_none_or(|c| c.height <= voxel.position.y) {
+                    columns[i] = Some(Column {
+                        height: voxel.position.y,
+                        color: voxel.color,
+                        is_water: voxel.is_water != 0,
+                    });
+                }
+            }
+            markers.extend(chunk.spawn_points);
+        }
+    }
+
+    let mut pixels: Vec<[u8; 3]> = Vec::with_capacity(pixel_count);
+    for z in origin.y..origin.y + size.y {
+        for x in origin.x..origin.x + size.x {
+            // Chunks outside of the world are left black
+            let Some(column) = columns[index(x, z).unwrap()] else {
+                pixels.push([0, 0, 0]);
+                continue;
+            };
+            // Shade by the ground, not by the objects standing on it
+            let ground = terrain_config.sample(x as f32, z as f32).trunc();
+            let height = (ground / terrain_config.max_height).clamp(0.0, 1.0);
+            let color = match export.view {
+                MapView::Height | MapView::Objects => Vec4::new(height, height, height, 1.0),
+                MapView::Ground => column.color,
+                MapView::Biome => match biome_config.get_biome(x, z) {
+                    Biome::Forest => Vec4::new(0.05, 0.35, 0.1, 1.0),
+                    Biome::Field => Vec4::new(0.4, 0.75, 0.3, 1.0),
+                    Biome::Desert => Vec4::new(0.9, 0.8, 0.4, 1.0),
+                },
+                MapView::Water if column.is_water => {
+                    let depth =
+                        terrain_config.min_height - terrain_config.sample(x as f32, z as f32);
+                    WATER_BLUE * (1.0 - (depth / terrain_config.min_height).clamp(0.0, 0.7))
+                }
+                MapView::Water => Vec4::splat(0.2 + 0.3 * height),
+            };
+            pixels.push(to_rgb(color));
+        }
+    }
+
+    if export.view == MapView::Objects {
+        for marker in markers {
+            let center = marker.instance_data.position;
+            let radius = match marker.spawn_type {
+                SpawnType::Tree => 2,
+                _ => 0,
+            };
+            for dz in -radius..=radius {
+                for dx in -radius..=radius {
+                    if let Some(i) = index(center.x as i32 + dx, center.z as i32 + dz) {
+                        pixels[i] = to_rgb(marker.instance_data.color);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn to_rgb(color: Vec4) -> [u8; 3] {
+    let c = (color.truncate().clamp(Vec3::ZERO, Vec3::ONE) * 255.0).round();
+    [c.x as u8, c.y as u8, c.z as u8]
+}
+
+/// Whether an image written to `path` is a PPM rather than a PNG, by its file extension. Fails
+/// for other extensions.
+fn is_ppm(path: &Path) -> io::Result<bool> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "ppm" => Ok(true),
+        "png" => Ok(false),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "map output must end in .ppm or .png",
+        )),
+    }
+}
+
+/// Writes an RGB image as PPM or PNG depending on the file extension.
+pub fn write_image(path: &Path, width: u32, height: u32, pixels: &[[u8; 3]]) -> io::Result<()> {
+    let ppm = is_ppm(path)?;
+    let mut out = BufWriter::new(File::create(path)?);
+    if ppm {
+        write!(out, "P6\n{width} {height}\n255\n")?;
+        out.write_all(pixels.as_flattened())?;
+    } else {
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()?
+            .write_image_data(pixels.as_flattened())?;
+    }
+    out.flush()
+}
+
+/// Renders the map and writes it to `export.output`.
+pub fn export_map(
+    export: &MapExport,
+    terrain_config: &TerrainConfig,
+    biome_config: &BiomeConfig,
+) -> io::Result<()> {
+    // Don't spend time rendering a map that can't be written
+    is_ppm(&export.output)?;
+    let pixels = render_map(export, terrain_config, biome_config)?;
+    write_image(
+        &export.output,
+        export.size.x as u32,
+        export.size.y as u32,
+        &pixels,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_sizes_are_checked() {
+        let mut export = MapExport::new("map.png".into());
+        assert_eq!(pixel_count(&export).unwrap(), 512 * 512);
+        export.size = IVec2::new(0, 10);
+        assert!(pixel_count(&export).is_err());
+        // The last column would be past the largest world coordinate
+        export.origin = IVec2::new(256, 0);
+        export.size = IVec2::new(i32::MAX, 10);
+        assert!(pixel_count(&export).is_err());
+        export.origin = IVec2::new(-256, 0);
+        assert_eq!(pixel_count(&export).unwrap(), 10 * i32::MAX as usize);
+    }
+
+    #[test]
+    fn output_extensions() {
+        assert!(is_ppm(Path::new("map.PPM")).unwrap());
+        assert!(!is_ppm(Path::new("map.png")).unwrap());
+        assert!(is_ppm(Path::new("map.jpg")).is_err());
+        assert!(is_ppm(Path::new("map")).is_err());
+    }
+}
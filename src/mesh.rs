@@ -0,0 +1,323 @@
+//! Triangle meshes of chunks, as an alternative to drawing every voxel as a cube instance.
+//!
+//! Only faces that can be seen are emitted, and neighbouring faces of the same material facing
+//! the same way are merged into larger quads (greedy meshing). Water moves with the waves, so
+//! its faces are never merged and every vertex remembers which voxel it belongs to.
+//!
+//! Every vertex also gets an ambient occlusion term from the three voxels touching its corner
+//! in front of the face, which darkens creases and corners. Faces are only merged when none of
+//! their corners are occluded differently, so merging never changes how the mesh is shaded.
+
+use glam::{IVec2, IVec3, Vec3, Vec4};
+
+use crate::voxels::{ChunkVoxels, MaterialId};
+
+/// A vertex in a chunk mesh. This is uploaded as-is to the GPU.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MeshVertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub color: Vec4,
+    /// Center of the voxel the vertex belongs to, which decides how water moves.
+    pub voxel: Vec3,
+    /// How much light reaches the corner, from 0 (fully occluded) to 1.
+    pub ao: f32,
+    pub is_water: u32,
+}
+
+/// A visible face in a slice of the chunk.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Face {
+    id: MaterialId,
+    /// Ambient occlusion of the corners at (-u, -v), (+u, -v), (+u, +v) and (-u, +v), from 0
+    /// (fully occluded) to 3.
+    ao: [u8; 4],
+}
+
+#[derive(Clone, Default)]
+pub struct ChunkMesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    fn push_quad(
+        &mut self,
+        corners: [Vec3; 4],
+        ao: [u8; 4],
+        normal: Vec3,
+        color: Vec4,
+        voxel: Option<Vec3>,
+    ) {
+        let first = self.vertices.len() as u32;
+        for (position, ao) in corners.into_iter().zip(ao) {
+            self.vertices.push(MeshVertex {
+                position,
+                normal,
+                color,
+                voxel: voxel.unwrap_or(position),
+                ao: ao as f32 / 3.0,
+                is_water: voxel.is_some() as u32,
+            });
+        }
+        // Split the quad along the brighter diagonal, otherwise the occlusion of one corner
+        // bleeds into the triangle on the other side
+        let triangles = if ao[0] + ao[2] < ao[1] + ao[3] {
+            [1, 2, 3, 1, 3, 0]
+        } else {
+            [0, 1, 2, 0, 2, 3]
+        };
+        self.indices
+            .extend(triangles.into_iter().map(|i| first + i));
+    }
+}
+
+/// How much coarser each level of detail is than the full chunk, in voxels per side.
+pub const LOD_FACTORS: [i32; 3] = [2, 4, 8];
+
+/// Meshes a chunk in world coordinates. `origin` is the world coordinate of the chunk's corner.
+pub fn greedy_mesh(voxels: &ChunkVoxels, origin: IVec2) -> ChunkMesh {
+    scaled_greedy_mesh(voxels, origin, 1)
+}
+
+/// Mesh of the chunk at level of detail `lod`, where 0 is full detail and the other levels
+/// are coarser by the factors in [`LOD_FACTORS`].
+///
+/// Every chunk mesh has walls where it ends, since voxels outside the chunk count as air, so
+/// there are no cracks between neighbouring chunks with different levels of detail.
+pub fn lod_mesh(voxels: &ChunkVoxels, origin: IVec2, lod: usize) -> ChunkMesh {
+    match lod {
+        0 => greedy_mesh(voxels, origin),
+        lod => {
+            let factor = LOD_FACTORS[lod - 1];
+            scaled_greedy_mesh(&voxels.downsample(factor), origin, factor)
+        }
+    }
+}
+
+/// Meshes a grid where every voxel is `scale` voxels wide in the world, like the ones from
+/// [`ChunkVoxels::downsample`].
+fn scaled_greedy_mesh(voxels: &ChunkVoxels, origin: IVec2, scale: i32) -> ChunkMesh {
+    let mut mesh = ChunkMesh::default();
+    let (min_y, max_y) = voxels.y_range();
+    let offset = IVec3::new(origin.x, 0, origin.y).as_vec3();
+    // Size of the grid, indexed by axis
+    let size = IVec3::new(voxels.width(), max_y - min_y, voxels.depth());
+
+    for axis in 0..3 {
+        // The two axes spanning the faces, ordered so that u x v points along `axis`
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let (width, height) = (size[u] as usize, size[v] as usize);
+        let mut mask: Vec<Option<Face>> = vec![None; width * height];
+
+        for direction in [-1, 1] {
+            let mut normal = IVec3::ZERO;
+            normal[axis] = direction;
+
+            for slice in 0..size[axis] {
+                let at = |i: usize, j: usize| {
+                    let mut p = IVec3::ZERO;
+                    p[axis] = slice;
+                    p[u] = i as i32;
+                    p[v] = j as i32;
+                    // The grid is indexed from min_y, but ChunkVoxels uses world y
+                    p + IVec3::new(0, min_y, 0)
+                };
+
+                // Which material each face in this slice has, if it can be seen
+                for j in 0..height {
+                    for i in 0..width {
+                        let p = at(i, j);
+                        let id = voxels.get_id(p);
+                        let visible = match voxels.material(id) {
+                            Some(material) if material.is_water => {
+                                let neighbour = voxels.get(p + normal);
+                                !neighbour.is_some_and(|n| n.is_water)
+                                    && !voxels.is_opaque(p + normal)
+                            }
+                            Some(_) => !voxels.is_opaque(p + normal),
+                            None => false,
+                        };
+                        mask[j * width + i] = visible.then(|| Face {
+                            id,
+                            ao: corner_ao(voxels, p + normal, u, v),
+                        });
+                    }
+                }
+
+                for j in 0..height {
+                    let mut i = 0;
+                    while i < width {
+                        let Some(face) = mask[j * width + i] else {
+                            i += 1;
+                            continue;
+                        };
+                        let material = voxels.material(face.id).unwrap();
+                        // Merged quads are shaded by their corners only, so faces that are
+                        // darker on one side can't be stretched
+                        let uniform_ao = face.ao.iter().all(|&ao| ao == face.ao[0]);
+                        let (w, h) = if material.is_water || !uniform_ao {
+                            (1, 1)
+                        } else {
+                            merge(&mask, width, height, i, j, face)
+                        };
+                        for row in mask[j * width..(j + h) * width].chunks_mut(width) {
+                            row[i..i + w].fill(None);
+                        }
+
+                        // Corners in grid coordinates are on the voxel boundaries, which are
+                        // half a voxel from the centers in the world
+                        let p = at(i, j);
+                        let corner = |du: usize, dv: usize| {
+                            let mut c = p.as_vec3();
+                            c[axis] += (direction > 0) as i32 as f32;
+                            c[u] += du as f32;
+                            c[v] += dv as f32;
+                            c * scale as f32 - 0.5 + offset
+                        };
+                        let mut corners = [corner(0, 0), corner(w, 0), corner(w, h), corner(0, h)];
+                        let mut ao = face.ao;
+                        // Counter-clockwise when seen from the side the face is facing
+                        if direction < 0 {
+                            corners.reverse();
+                            ao.reverse();
+                        }
+                        let voxel = material
+                            .is_water
+                            .then(|| (p.as_vec3() + 0.5) * scale as f32 - 0.5 + offset);
+                        mesh.push_quad(corners, ao, normal.as_vec3(), material.color, voxel);
+                        i += w;
+                    }
+                }
+            }
+        }
+    }
+    mesh
+}
+
+/// Ambient occlusion of the four corners of a face, given the voxel in front of it. A corner
+/// with both sides blocked is fully occluded no matter what is in the corner itself.
+fn corner_ao(voxels: &ChunkVoxels, front: IVec3, u: usize, v: usize) -> [u8; 4] {
+    [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(du, dv)| {
+        let (mut step_u, mut step_v) = (IVec3::ZERO, IVec3::ZERO);
+        step_u[u] = du;
+        step_v[v] = dv;
+        let side_u = voxels.is_opaque(front + step_u);
+        let side_v = voxels.is_opaque(front + step_v);
+        let corner = voxels.is_opaque(front + step_u + step_v);
+        if side_u && side_v {
+            0
+        } else {
+            3 - side_u as u8 - side_v as u8 - corner as u8
+        }
+    })
+}
+
+/// Grows a quad of faces equal to `face` from (i, j), first along the row and then as many
+/// rows as fit.
+fn merge(
+    mask: &[Option<Face>],
+    width: usize,
+    height: usize,
+    i: usize,
+    j: usize,
+    face: Face,
+) -> (usize, usize) {
+    let mut w = 1;
+    while i + w < width && mask[j * width + i + w] == Some(face) {
+        w += 1;
+    }
+    let mut h = 1;
+    while j + h < height
+        && mask[(j + h) * width + i..(j + h) * width + i + w]
+            .iter()
+            .all(|&m| m == Some(face))
+    {
+        h += 1;
+    }
+    (w, h)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxels::Material;
+
+    use super::*;
+
+    const STONE: Vec4 = Vec4::new(0.5, 0.5, 0.5, 1.0);
+    const GRASS: Vec4 = Vec4::new(0.2, 0.6, 0.2, 1.0);
+
+    fn mesh(voxels: &[(IVec3, Material)]) -> ChunkMesh {
+        greedy_mesh(&ChunkVoxels::from_voxels(4, 4, voxels), IVec2::ZERO)
+    }
+
+    fn quads(mesh: &ChunkMesh) -> usize {
+        assert_eq!(mesh.vertices.len() * 6, mesh.indices.len() * 4);
+        mesh.indices.len() / 6
+    }
+
+    #[test]
+    fn a_single_voxel_is_a_cube() {
+        let voxels = ChunkVoxels::from_voxels(4, 4, &[(IVec3::new(1, 2, 3), Material::new(STONE))]);
+        let mesh = greedy_mesh(&voxels, IVec2::new(16, -32));
+        assert_eq!(quads(&mesh), 6);
+        let center = Vec3::new(17.0, 2.0, -29.0);
+        for vertex in &mesh.vertices {
+            assert_eq!((vertex.position - center).abs(), Vec3::splat(0.5));
+            assert_eq!(vertex.color, STONE);
+        }
+    }
+
+    #[test]
+    fn triangles_face_outwards() {
+        let mesh = mesh(&[
+            (IVec3::new(0, 0, 0), Material::new(STONE)),
+            (IVec3::new(1, 0, 0), Material::new(STONE)),
+            (IVec3::new(1, 1, 0), Material::new(GRASS)),
+        ]);
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize]);
+            let facing = (b.position - a.position).cross(c.position - a.position);
+            assert!(facing.dot(a.normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn faces_of_the_same_material_are_merged() {
+        let slab: Vec<(IVec3, Material)> = (0..16)
+            .map(|i| (IVec3::new(i % 4, 0, i / 4), Material::new(STONE)))
+            .collect();
+        assert_eq!(quads(&mesh(&slab)), 6);
+    }
+
+    #[test]
+    fn faces_of_different_materials_are_not_merged() {
+        let mesh = mesh(&[
+            (IVec3::new(0, 0, 0), Material::new(STONE)),
+            (IVec3::new(1, 0, 0), Material::new(GRASS)),
+        ]);
+        // Two faces each on top, bottom, front and back, one on each end, none between them
+        assert_eq!(quads(&mesh), 10);
+    }
+
+    #[test]
+    fn water_faces_are_not_merged() {
+        let water = Material::water(Vec4::new(0.0, 0.3, 0.8, 0.5));
+        let mesh = mesh(&[(IVec3::new(0, 0, 0), water), (IVec3::new(1, 0, 0), water)]);
+        assert_eq!(quads(&mesh), 10);
+        assert!(mesh.vertices.iter().all(|v| v.is_water == 1));
+    }
+
+    #[test]
+    fn covered_faces_are_left_out() {
+        let cube: Vec<(IVec3, Material)> = (0..27)
+            .map(|i| (IVec3::new(i % 3, i / 9, i / 3 % 3), Material::new(STONE)))
+            .collect();
+        let mesh = mesh(&cube);
+        // The outside of the cube, with no faces inside it
+        let inside = |p: Vec3| p.cmpgt(Vec3::splat(-0.5)).all() && p.cmplt(Vec3::splat(2.5)).all();
+        assert!(mesh.vertices.iter().all(|v| !inside(v.position)));
+        assert_eq!(quads(&mesh), 6);
+    }
+}
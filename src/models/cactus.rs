@@ -0,0 +1,45 @@
+use glam::{Quat, Vec3, Vec4};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::models::Model;
+use crate::InstanceData;
+
+const CACTUS_GREEN: Vec4 = Vec4::new(0.2, 0.5, 0.2, 1.0);
+
+pub fn cactus(seed: u64, translation: Vec3) -> Vec<Model> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let height = rng.gen_range(5..12);
+    let mut points: Vec<Vec3> = (0..height).map(|y| Vec3::new(0.0, y as f32, 0.0)).collect();
+
+    // Arms go out from the trunk and then bend upwards
+    let arms = rng.gen_range(0..=2);
+    for arm in 0..arms {
+        let direction = if arm == 0 { 1.0 } else { -1.0 };
+        let start = rng.gen_range(2..height - 2);
+        let reach = rng.gen_range(1..=2);
+        let rise = rng.gen_range(2..=4);
+        for x in 1..=reach {
+            points.push(Vec3::new(direction * x as f32, start as f32, 0.0));
+        }
+        for y in 1..=rise {
+            points.push(Vec3::new(direction * reach as f32, (start + y) as f32, 0.0));
+        }
+    }
+
+    let points = points
+        .into_iter()
+        .map(|position| InstanceData {
+            position,
+            color: CACTUS_GREEN,
+            is_water: 0,
+        })
+        .collect();
+    // Turn the cactus so the arms don't all point the same way
+    let turns = rng.gen_range(0..4) as f32;
+    vec![Model {
+        points,
+        rotation: Quat::from_rotation_y(turns * std::f32::consts::FRAC_PI_2),
+        translation,
+    }]
+}
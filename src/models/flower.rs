@@ -1,18 +1,18 @@
 use glam::{Quat, Vec3};
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::models::Model;
 use crate::utils::{BLUE, BROWN, ORANGE, PURPLE, RED, YELLOW};
 use crate::InstanceData;
 
-fn generate_rand_rot() -> Vec3 {
-    let mut rng = rand::thread_rng();
+fn generate_rand_rot(rng: &mut impl Rng) -> Vec3 {
     let x: f32 = rng.gen::<f32>() * 2. - 1.;
     let z: f32 = rng.gen::<f32>() * 2. - 1.;
     Vec3::new(x, 0.8, z).normalize()
 }
 
-fn gen_blue_flower(translation: Vec3) -> Model {
+fn gen_blue_flower(rng: &mut impl Rng, translation: Vec3) -> Model {
     Model {
         #[rustfmt::skip]
         points: vec![
@@ -23,12 +23,12 @@ fn gen_blue_flower(translation: Vec3) -> Model {
             InstanceData { position: Vec3::new( 0.0, 0.0, -1.0), color: BLUE,   is_water: 0},
         ],
 
-        rotation: Quat::from_rotation_arc(Vec3::Y, generate_rand_rot()),
+        rotation: Quat::from_rotation_arc(Vec3::Y, generate_rand_rot(rng)),
         translation,
     }
 }
 
-fn gen_red_flower(translation: Vec3) -> Model {
+fn gen_red_flower(rng: &mut impl Rng, translation: Vec3) -> Model {
     Model {
         #[rustfmt::skip]
         points: vec![
@@ -47,12 +47,12 @@ fn gen_red_flower(translation: Vec3) -> Model {
             InstanceData { position: Vec3::new( 0.0, 1.0, -1.0), color: RED, is_water: 0},
         ],
 
-        rotation: Quat::from_rotation_arc(Vec3::Y, generate_rand_rot()),
+        rotation: Quat::from_rotation_arc(Vec3::Y, generate_rand_rot(rng)),
         translation,
     }
 }
 
-fn gen_purple_flower(translation: Vec3) -> Model {
+fn gen_purple_flower(rng: &mut impl Rng, translation: Vec3) -> Model {
     Model {
         #[rustfmt::skip]
         points: vec![
@@ -71,14 +71,36 @@ fn gen_purple_flower(translation: Vec3) -> Model {
             InstanceData { position: Vec3::new( 0.0, 1.0, -2.0), color: PURPLE, is_water: 0},
         ],
 
-        rotation: Quat::from_rotation_arc(Vec3::Y, generate_rand_rot()),
+        rotation: Quat::from_rotation_arc(Vec3::Y, generate_rand_rot(rng)),
         translation,
     }
 }
 
-pub fn proc_gen_flower(_seed: u64, translation: Vec3) -> Vec<Model> {
-    let mut rng = rand::thread_rng();
-    let stem_length = rand::thread_rng().gen_range(3..9);
+/// The kinds of flower heads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowerSpecies {
+    Red,
+    Blue,
+    Purple,
+}
+
+/// A flower of a random species.
+pub fn proc_gen_flower(seed: u64, translation: Vec3) -> Vec<Model> {
+    let rand: f32 = StdRng::seed_from_u64(seed).gen::<f32>();
+    let species = if rand < 0.33 {
+        FlowerSpecies::Red
+    } else if rand < 0.67 {
+        FlowerSpecies::Blue
+    } else {
+        FlowerSpecies::Purple
+    };
+    flower(seed, translation, species)
+}
+
+pub fn flower(seed: u64, translation: Vec3, species: FlowerSpecies) -> Vec<Model> {
+    // Offset the seed so the stem doesn't correlate with the species in proc_gen_flower
+    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(1));
+    let stem_length = rng.gen_range(3..9);
     let mut flower_stem: Vec<InstanceData> = vec![];
     for y in 0..stem_length {
         flower_stem.push(InstanceData {
@@ -92,18 +114,14 @@ pub fn proc_gen_flower(_seed: u64, translation: Vec3) -> Vec<Model> {
         rotation: Quat::IDENTITY,
         translation,
     };
-    let flower: Model;
-    let rand: f32 = rng.gen::<f32>();
     let mut flower_translation = translation;
     flower_translation.y += stem_length as f32 - 0.2;
 
-    if rand < 0.33 {
-        flower = gen_red_flower(flower_translation);
-    } else if rand < 0.67 {
-        flower = gen_blue_flower(flower_translation);
-    } else {
-        flower = gen_purple_flower(flower_translation);
-    }
+    let flower = match species {
+        FlowerSpecies::Red => gen_red_flower(&mut rng, flower_translation),
+        FlowerSpecies::Blue => gen_blue_flower(&mut rng, flower_translation),
+        FlowerSpecies::Purple => gen_purple_flower(&mut rng, flower_translation),
+    };
 
     vec![stem, flower]
 }
@@ -0,0 +1,333 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read as _};
+use std::path::Path;
+use std::str::FromStr;
+
+/// What to do when sampling outside of the heightmap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeightmapWrap {
+    /// Repeat the heightmap in every direction.
+    Tile,
+    /// Extend the edge pixels in every direction.
+    Clamp,
+}
+
+impl FromStr for HeightmapWrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tile" => Ok(HeightmapWrap::Tile),
+            "clamp" => Ok(HeightmapWrap::Clamp),
+            _ => Err(format!(
+                "invalid heightmap wrap {s:?}, expected clamp or tile"
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Io(io::Error),
+    Png(png::DecodingError),
+    /// The file isn't a PGM or PNG file, or it is in a variant we can't read.
+    Format(String),
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightmapError::Io(e) => write!(f, "couldn't read heightmap: {e}"),
+            HeightmapError::Png(e) => write!(f, "couldn't decode PNG heightmap: {e}"),
+            HeightmapError::Format(e) => write!(f, "unsupported heightmap: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {}
+
+impl From<io::Error> for HeightmapError {
+    fn from(e: io::Error) -> Self {
+        HeightmapError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for HeightmapError {
+    fn from(e: png::DecodingError) -> Self {
+        HeightmapError::Png(e)
+    }
+}
+
+/// A greyscale image used as terrain height.
+///
+/// The image is centered on the world origin. Each pixel covers `scale` voxels and pixel values
+/// are normalized to [0, 1], where 1 is the brightest possible value for the file's bit depth.
+#[derive(Clone)]
+pub struct Heightmap {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+    pub scale: f32,
+    pub wrap: HeightmapWrap,
+}
+
+impl Heightmap {
+    /// Reads a PGM (P2 or P5) or an 8/16-bit PNG file. The format is detected from the file
+    /// contents, not the file extension.
+    pub fn load(
+        path: impl AsRef<Path>,
+        scale: f32,
+        wrap: HeightmapWrap,
+    ) -> Result<Heightmap, HeightmapError> {
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        let (width, height, values) = decode(&bytes)?;
+        Ok(Heightmap {
+            width,
+            height,
+            values,
+            scale,
+            wrap,
+        })
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> f32 {
+        let (w, h) = (self.width as i64, self.height as i64);
+        let (x, y) = match self.wrap {
+            HeightmapWrap::Tile => (x.rem_euclid(w), y.rem_euclid(h)),
+            HeightmapWrap::Clamp => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+        };
+        self.values[y as usize * self.width + x as usize]
+    }
+
+    /// Bilinearly interpolated height in [0, 1] at world coordinate (x, z).
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let px = x / self.scale + self.width as f32 / 2.0;
+        let py = z / self.scale + self.height as f32 / 2.0;
+        let (x0, y0) = (px.floor(), py.floor());
+        let (tx, ty) = (px - x0, py - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(x0, y0) * (1.0 - tx) + self.pixel(x0 + 1, y0) * tx;
+        let bottom = self.pixel(x0, y0 + 1) * (1.0 - tx) + self.pixel(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+/// Decodes a PGM or PNG file into its width, height and normalized pixel values.
+fn decode(bytes: &[u8]) -> Result<(usize, usize, Vec<f32>), HeightmapError> {
+    if bytes.starts_with(b"\x89PNG") {
+        decode_png(bytes)
+    } else if bytes.starts_with(b"P2") || bytes.starts_with(b"P5") {
+        decode_pgm(bytes)
+    } else {
+        Err(HeightmapError::Format(
+            "expected a PGM or PNG file".to_string(),
+        ))
+    }
+}
+
+fn decode_png(bytes: &[u8]) -> Result<(usize, usize, Vec<f32>), HeightmapError> {
+    let mut decoder = png::Decoder::new(bytes);
+    // Expands bit depths below 8 to 8 bits, but keeps 16 bit samples
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        color_type => {
+            return Err(HeightmapError::Format(format!(
+                "expected a greyscale PNG, got {color_type:?}"
+            )))
+        }
+    };
+    let pixels = info.width as usize * info.height as usize;
+    let values = match info.bit_depth {
+        png::BitDepth::Sixteen => buf
+            .chunks_exact(2 * channels)
+            .take(pixels)
+            .map(|p| u16::from_be_bytes([p[0], p[1]]) as f32 / u16::MAX as f32)
+            .collect(),
+        _ => buf
+            .chunks_exact(channels)
+            .take(pixels)
+            .map(|p| p[0] as f32 / u8::MAX as f32)
+            .collect(),
+    };
+    Ok((info.width as usize, info.height as usize, values))
+}
+
+fn decode_pgm(bytes: &[u8]) -> Result<(usize, usize, Vec<f32>), HeightmapError> {
+    let binary = bytes.starts_with(b"P5");
+    let mut pos = 2;
+
+    // The header is the magic number followed by width, height and max value, separated by
+    // whitespace and possibly interleaved with comments.
+    let mut header = [0u32; 3];
+    for value in header.iter_mut() {
+        loop {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < bytes.len() && bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = pos;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        *value = std::str::from_utf8(&bytes[start..pos])
+            .unwrap()
+            .parse()
+            .map_err(|_| HeightmapError::Format("malformed PGM header".to_string()))?;
+    }
+    let [width, height, max_value] = header.map(|v| v as usize);
+    if width == 0 || height == 0 || max_value == 0 || max_value > u16::MAX as usize {
+        return Err(HeightmapError::Format(format!(
+            "invalid PGM dimensions {width}x{height} with max value {max_value}"
+        )));
+    }
+
+    // Samples above the max value would make the terrain taller than its max height
+    let normalize = |sample: usize| {
+        if sample > max_value {
+            return Err(HeightmapError::Format(format!(
+                "PGM sample {sample} is above the max value {max_value}"
+            )));
+        }
+        Ok(sample as f32 / max_value as f32)
+    };
+    let pixels = width * height;
+    let values: Vec<f32> = if binary {
+        // Exactly one whitespace character separates the header from the raster
+        let raster = &bytes[(pos + 1).min(bytes.len())..];
+        if max_value > u8::MAX as usize {
+            raster
+                .chunks_exact(2)
+                .take(pixels)
+                .map(|p| normalize(u16::from_be_bytes([p[0], p[1]]) as usize))
+                .collect::<Result<_, _>>()?
+        } else {
+            raster
+                .iter()
+                .take(pixels)
+                .map(|&p| normalize(p as usize))
+                .collect::<Result<_, _>>()?
+        }
+    } else {
+        std::str::from_utf8(&bytes[pos..])
+            .map_err(|_| HeightmapError::Format("malformed PGM raster".to_string()))?
+            .split_ascii_whitespace()
+            .take(pixels)
+            .map(|v| match v.parse() {
+                Ok(sample) => normalize(sample),
+                Err(_) => Err(HeightmapError::Format(format!(
+                    "malformed PGM sample {v:?}"
+                ))),
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    if values.len() != pixels {
+        return Err(HeightmapError::Format(format!(
+            "PGM raster is truncated, expected {pixels} pixels but got {}",
+            values.len()
+        )));
+    }
+    Ok((width, height, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(bit_depth: png::BitDepth, color: png::ColorType, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+        encoder.set_depth(bit_depth);
+        encoder.set_color(color);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn ascii_pgm() {
+        let bytes = b"P2\n# a comment\n3 1\n# another\n4\n0 2\n4\n";
+        assert_eq!(decode(bytes).unwrap(), (3, 1, vec![0.0, 0.5, 1.0]));
+    }
+
+    #[test]
+    fn binary_pgm() {
+        assert_eq!(
+            decode(b"P5 2 1 255\n\x00\xff").unwrap(),
+            (2, 1, vec![0.0, 1.0])
+        );
+        // Samples above 255 take two bytes, most significant first
+        assert_eq!(
+            decode(b"P5 2 1 1000\n\x01\xf4\x03\xe8").unwrap(),
+            (2, 1, vec![0.5, 1.0])
+        );
+    }
+
+    #[test]
+    fn pgm_samples_above_the_max_value_are_errors() {
+        assert!(decode(b"P2 2 1 4 0 5").is_err());
+        assert!(decode(b"P5 2 1 4\n\x00\x05").is_err());
+        assert!(decode(b"P2 2 1 4 0 -1").is_err());
+        assert!(decode(b"P2 2 1 4 0 1.5").is_err());
+    }
+
+    #[test]
+    fn malformed_pgm_headers_are_errors() {
+        assert!(decode(b"P2").is_err());
+        assert!(decode(b"P2 2 x 4 0 1").is_err());
+        assert!(decode(b"P2 0 1 4").is_err());
+        assert!(decode(b"P2 2 1 0 0 0").is_err());
+        assert!(decode(b"P5 2 1 70000\n\x00\x00\x00\x00").is_err());
+        // Truncated raster
+        assert!(decode(b"P2 2 2 4 0 1 2").is_err());
+        assert!(decode(b"P6 2 1 255\n\x00\x00").is_err());
+    }
+
+    #[test]
+    fn eight_bit_png() {
+        let bytes = png(png::BitDepth::Eight, png::ColorType::Grayscale, &[0, 255]);
+        assert_eq!(decode(&bytes).unwrap(), (2, 1, vec![0.0, 1.0]));
+        // Alpha is ignored
+        let bytes = png(
+            png::BitDepth::Eight,
+            png::ColorType::GrayscaleAlpha,
+            &[255, 0, 0, 255],
+        );
+        assert_eq!(decode(&bytes).unwrap(), (2, 1, vec![1.0, 0.0]));
+    }
+
+    #[test]
+    fn sixteen_bit_png() {
+        let bytes = png(
+            png::BitDepth::Sixteen,
+            png::ColorType::Grayscale,
+            &[0xff, 0xff, 0x00, 0x00],
+        );
+        assert_eq!(decode(&bytes).unwrap(), (2, 1, vec![1.0, 0.0]));
+    }
+
+    #[test]
+    fn colour_pngs_are_errors() {
+        let bytes = png(png::BitDepth::Eight, png::ColorType::Rgb, &[0; 6]);
+        assert!(decode(&bytes).is_err());
+    }
+}
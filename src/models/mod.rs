@@ -1,20 +1,74 @@
-use glam::{Quat, Vec3};
-
-use crate::InstanceData;
+use glam::{Quat, Vec3, Vec4};
 
 pub mod biomes;
+pub mod cactus;
 pub mod flower;
+pub mod heightmap;
 pub mod primitives;
 pub mod rock;
 pub mod terrain;
 pub mod tree;
 
+pub use cactus::cactus;
 pub use rock::rock;
 pub use tree::tree;
 
+use flower::{flower, FlowerSpecies};
+
+/// A single voxel. This is uploaded as-is to the GPU as instance data.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct InstanceData {
+    pub position: Vec3,
+    pub color: Vec4,
+    pub is_water: u32,
+}
+
 #[derive(Clone)]
 pub struct Model {
     pub points: Vec<InstanceData>,
     pub rotation: Quat,
     pub translation: Vec3,
 }
+
+/// The objects that can be planted in the world.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Generator {
+    Flower(FlowerSpecies),
+    Tree,
+    Rock,
+    Cactus,
+}
+
+impl Generator {
+    pub const ALL: [Generator; 6] = [
+        Generator::Flower(FlowerSpecies::Red),
+        Generator::Flower(FlowerSpecies::Blue),
+        Generator::Flower(FlowerSpecies::Purple),
+        Generator::Tree,
+        Generator::Rock,
+        Generator::Cactus,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Generator::Flower(FlowerSpecies::Red) => "red flower",
+            Generator::Flower(FlowerSpecies::Blue) => "blue flower",
+            Generator::Flower(FlowerSpecies::Purple) => "purple flower",
+            Generator::Tree => "tree",
+            Generator::Rock => "rock",
+            Generator::Cactus => "cactus",
+        }
+    }
+
+    /// Generates the object with its base at `translation`. The same seed always gives the
+    /// same object.
+    pub fn generate(&self, seed: u64, translation: Vec3) -> Vec<Model> {
+        match *self {
+            Generator::Flower(species) => flower(seed, translation, species),
+            Generator::Tree => tree(seed, translation),
+            Generator::Rock => rock(seed, translation),
+            Generator::Cactus => cactus(seed, translation),
+        }
+    }
+}
@@ -1,15 +1,14 @@
 use crate::models::primitives::sphere;
 use crate::models::Model;
-use rand::{thread_rng, Rng};
-// use rand_chacha::ChaCha8Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::utils::GREY;
 use crate::{InstanceData, Point};
 use glam::{Quat, Vec3};
 
-pub fn rock(_seed: u64, translation: Vec3) -> Vec<Model> {
-    // let rng = ChaCha8Rng::from_seed(_seed);
-    let mut rng = thread_rng();
+pub fn rock(seed: u64, translation: Vec3) -> Vec<Model> {
+    let mut rng = StdRng::seed_from_u64(seed);
     let mut rocks: Vec<Model> = vec![];
     let pebbles: i32 = rng.gen_range(1..=5);
     for _x in 1..=pebbles {
@@ -1,12 +1,37 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use crate::frustum::Aabb;
+use crate::mesh::{lod_mesh, ChunkMesh, LOD_FACTORS};
 use crate::models::biomes::{Biome, BiomeConfig};
 use crate::models::flower::proc_gen_flower;
-use crate::models::{rock, tree, Model};
+use crate::models::heightmap::Heightmap;
+use crate::models::{cactus, rock, tree, Model};
 use crate::utils::{BROWN, GREEN, GREY, RED, WATER_BLUE};
+use crate::voxels::{voxel_at, ChunkVoxels, Material};
 use crate::InstanceData;
-use glam::{Vec3, Vec4};
+use glam::{IVec2, IVec3, Vec2, Vec3, Vec4};
 use noise::{NoiseFn, Perlin};
 
-type Object = Vec<Model>;
+/// An object standing on the ground, like a tree, made up of one or more models.
+#[derive(Clone)]
+pub struct Object {
+    /// Tells the object apart from the other objects in its chunk. Generated objects are
+    /// numbered by the column they stand on, so they get the same id every time the chunk is
+    /// generated. Planted objects get theirs from [`crate::Chunks`].
+    pub id: u64,
+    pub models: Vec<Model>,
+}
+
+impl Object {
+    /// Whether the object is large enough to be drawn as a proxy far away. Small objects like
+    /// flowers and rocks are always drawn at full detail.
+    pub fn has_proxy(&self) -> bool {
+        let voxels: usize = self.models.iter().map(|model| model.points.len()).sum();
+        voxels >= PROXY_MIN_VOXELS
+    }
+}
 
 #[derive(Clone)]
 pub enum SpawnType {
@@ -16,6 +41,76 @@ pub enum SpawnType {
     Rock,
 }
 
+const GRASS: Vec4 = Vec4::new(0.1, 0.5, 0.2, 1.0);
+const SAND: Vec4 = Vec4::new(0.7, 0.7, 0.1, 1.0);
+const BEACH: Vec4 = Vec4::new(0.8, 0.75, 0.45, 1.0);
+const BARE_ROCK: Vec4 = Vec4::new(0.45, 0.42, 0.4, 1.0);
+const SNOW: Vec4 = Vec4::new(0.95, 0.95, 1.0, 1.0);
+const WALL: Vec4 = Vec4::new(0.25, 0.22, 0.2, 1.0);
+
+/// How far the edge wall of a finite world reaches above `max_height`.
+const WALL_CLEARANCE: f32 = 8.0;
+
+/// How much of the world is generated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorldBounds {
+    /// Terrain continues forever in every direction.
+    Infinite,
+    /// A rectangle of `size` voxels centered on the origin, surrounded by a wall.
+    Finite { size: IVec2 },
+    /// An island of `radius` voxels centered on the origin. The terrain falls off towards the
+    /// edge and is surrounded by ocean.
+    Island { radius: f32 },
+}
+
+impl WorldBounds {
+    /// Voxel rectangle of a finite world, as (inclusive min, exclusive max).
+    fn finite_rect(size: IVec2) -> (IVec2, IVec2) {
+        let min = -size / 2;
+        (min, min + size)
+    }
+
+    /// How far outside the island radius chunks of ocean are still generated.
+    fn ocean_margin(radius: f32) -> f32 {
+        radius * 0.25
+    }
+}
+
+impl FromStr for WorldBounds {
+    type Err = String;
+
+    /// Parses `infinite`, `finite:<width>,<depth>` or `island:<radius>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid world bounds {s:?}, expected infinite, finite:<width>,<depth> or island:<radius>"
+            )
+        };
+        match s.split_once(':') {
+            None if s == "infinite" => Ok(WorldBounds::Infinite),
+            Some(("finite", size)) => {
+                let (w, d) = size.split_once(',').ok_or_else(invalid)?;
+                let size = IVec2::new(
+                    w.trim().parse().map_err(|_| invalid())?,
+                    d.trim().parse().map_err(|_| invalid())?,
+                );
+                if size.cmplt(IVec2::splat(3)).any() {
+                    return Err(format!("a finite world must be at least 3x3, got {size}"));
+                }
+                Ok(WorldBounds::Finite { size })
+            }
+            Some(("island", radius)) => {
+                let radius: f32 = radius.trim().parse().map_err(|_| invalid())?;
+                if !(radius > 0.0 && radius.is_finite()) {
+                    return Err(format!("island radius must be positive, got {radius}"));
+                }
+                Ok(WorldBounds::Island { radius })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TerrainConfig {
     pub sample_rate: f32,
@@ -24,16 +119,156 @@ pub struct TerrainConfig {
     pub depth: i32,
     pub max_height: f32,
     pub min_height: f32,
+    /// Fraction of `max_height` above which the ground is covered in snow.
+    pub snow_line: f32,
+    /// Fraction of `max_height` above which the ground is bare rock.
+    pub rock_line: f32,
+    /// Height difference between neighbouring voxels above which the ground is bare rock.
+    pub max_slope: f32,
     pub noise: Perlin,
+    /// If set, heights are read from this heightmap instead of sampled from `noise`.
+    pub heightmap: Option<Arc<Heightmap>>,
+    pub bounds: WorldBounds,
 }
 
 impl TerrainConfig {
     pub fn sample(&self, x: f32, z: f32) -> f32 {
-        let px = x * self.sample_rate;
-        let pz = z * self.sample_rate;
-        let sample = (self.noise.get([px as f64, pz as f64]) as f32 + 1.0) / 2.0;
-        self.max_height * sample
+        let sample = match &self.heightmap {
+            Some(heightmap) => heightmap.sample(x, z),
+            None => {
+                let px = x * self.sample_rate;
+                let pz = z * self.sample_rate;
+                (self.noise.get([px as f64, pz as f64]) as f32 + 1.0) / 2.0
+            }
+        };
+        let falloff = match self.bounds {
+            WorldBounds::Island { radius } => {
+                let d = Vec2::new(x, z).length() / radius;
+                // smoothstep from full height at 60% of the radius down to the sea floor
+                let t = ((d - 0.6) / 0.4).clamp(0.0, 1.0);
+                1.0 - t * t * (3.0 - 2.0 * t)
+            }
+            WorldBounds::Infinite | WorldBounds::Finite { .. } => 1.0,
+        };
+        self.max_height * sample * falloff
+    }
+
+    /// Whether the chunk with its corner at `chunk * (width, depth)` should be generated and
+    /// drawn.
+    pub fn chunk_in_bounds(&self, chunk: IVec2) -> bool {
+        let chunk_min = chunk * IVec2::new(self.width, self.depth);
+        let chunk_max = chunk_min + IVec2::new(self.width, self.depth);
+        match self.bounds {
+            WorldBounds::Infinite => true,
+            WorldBounds::Finite { size } => {
+                let (min, max) = WorldBounds::finite_rect(size);
+                chunk_min.cmplt(max).all() && chunk_max.cmpgt(min).all()
+            }
+            WorldBounds::Island { radius } => {
+                // Distance from the origin to the closest point of the chunk
+                let closest = IVec2::ZERO.clamp(chunk_min, chunk_max - 1).as_vec2();
+                closest.length() <= radius + WorldBounds::ocean_margin(radius)
+            }
+        }
+    }
+
+    /// Moves `position` horizontally so it is inside the world.
+    pub fn clamp_position(&self, position: Vec3) -> Vec3 {
+        match self.bounds {
+            WorldBounds::Infinite => position,
+            WorldBounds::Finite { size } => {
+                // Stay one voxel away from the wall on every side
+                let (min, max) = WorldBounds::finite_rect(size);
+                let (min, max) = ((min + 1).as_vec2(), (max - 2).as_vec2());
+                Vec3::new(
+                    position.x.clamp(min.x, max.x.max(min.x)),
+                    position.y,
+                    position.z.clamp(min.y, max.y.max(min.y)),
+                )
+            }
+            WorldBounds::Island { radius } => {
+                let horizontal = Vec2::new(position.x, position.z).clamp_length_max(radius);
+                Vec3::new(horizontal.x, position.y, horizontal.y)
+            }
+        }
     }
+
+    /// Whether the column at (x, z) is inside the world. Chunks on the edge of a finite world
+    /// are only partly inside it.
+    fn column_in_bounds(&self, x: i32, z: i32) -> bool {
+        match self.bounds {
+            WorldBounds::Finite { size } => {
+                let (min, max) = WorldBounds::finite_rect(size);
+                let p = IVec2::new(x, z);
+                p.cmpge(min).all() && p.cmplt(max).all()
+            }
+            WorldBounds::Infinite | WorldBounds::Island { .. } => true,
+        }
+    }
+
+    /// Whether (x, z) is part of the wall surrounding a finite world.
+    fn is_wall(&self, x: i32, z: i32) -> bool {
+        match self.bounds {
+            WorldBounds::Finite { size } => {
+                let (min, max) = WorldBounds::finite_rect(size);
+                let p = IVec2::new(x, z);
+                p.cmpge(min).all()
+                    && p.cmplt(max).all()
+                    && (p.cmpeq(min).any() || p.cmpeq(max - 1).any())
+            }
+            WorldBounds::Infinite | WorldBounds::Island { .. } => false,
+        }
+    }
+
+    /// Steepness of the terrain at (x, z), measured as the largest height difference per voxel
+    /// along the x- and z-axis.
+    pub fn slope(&self, x: f32, z: f32) -> f32 {
+        let dx = self.sample(x + 1.0, z).trunc() - self.sample(x - 1.0, z).trunc();
+        let dz = self.sample(x, z + 1.0).trunc() - self.sample(x, z - 1.0).trunc();
+        dx.abs().max(dz.abs()) / 2.0
+    }
+
+    /// Colour of the ground voxel at (x, z) with its top at `height`.
+    ///
+    /// High ground gets snow and bare rock, steep faces get exposed rock and ground just above
+    /// the water gets a beach. Everything else is coloured by the biome. The colour is jittered
+    /// slightly so large fields aren't one flat colour. Only depends on the position, so the
+    /// same voxel always gets the same colour.
+    pub fn ground_color(&self, biome: &Biome, x: i32, z: i32, height: f32) -> Vec4 {
+        let jitter = position_jitter(x, z);
+        // Move the boundaries between bands a bit so they aren't perfectly straight lines
+        let altitude = height / self.max_height + (jitter - 0.5) * 0.04;
+
+        let color = if altitude >= self.snow_line {
+            SNOW
+        } else if altitude >= self.rock_line || self.slope(x as f32, z as f32) > self.max_slope {
+            BARE_ROCK
+        } else if height <= self.min_height + 1.0 {
+            BEACH
+        } else {
+            match biome {
+                Biome::Forest | Biome::Field => GRASS,
+                Biome::Desert => SAND,
+            }
+        };
+
+        let shade = 1.0 + (jitter - 0.5) * 0.1;
+        (color.truncate() * shade).clamp(Vec3::ZERO, Vec3::ONE).extend(color.w)
+    }
+}
+
+/// Pseudo-random value in [0, 1) that only depends on the position.
+fn position_jitter(x: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4_eb2d) ^ (z as u32).wrapping_mul(0x1656_67b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    (h & 0xffff) as f32 / 65536.0
+}
+
+/// Seed for the object spawned at (x, z), so every object looks different.
+fn object_seed(x: i32, z: i32) -> u64 {
+    ((x as u32 as u64) << 32 | z as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
 }
 
 pub struct SpawnPoint {
@@ -50,24 +285,140 @@ impl SpawnPoint {
     }
 }
 
+/// How high the water shader can lift water, with the wave sliders at their maximum.
+const MAX_WAVE_HEIGHT: f32 = 2.0;
+
+/// Objects with fewer voxels than this, like flowers and rocks, are cheap enough that they
+/// don't get proxies.
+const PROXY_MIN_VOXELS: usize = 200;
+/// Size of the blocks that proxies are made of, in voxels per side.
+const PROXY_FACTOR: i32 = 2;
+
 pub struct GenerationPositions {
-    pub ground: Vec<InstanceData>,
+    /// World coordinate of the chunk's corner.
+    pub origin: IVec2,
+    /// Every voxel in the chunk, including the objects standing on it. `remesh` needs to be
+    /// called when they change.
+    pub voxels: ChunkVoxels,
+    /// Instance data derived from `voxels`, built the first time it is asked for.
+    instances: OnceLock<Vec<InstanceData>>,
+    /// Greedy meshes of `voxels`, one for full detail followed by one per level in
+    /// `LOD_FACTORS`. Each is built the first time it is asked for.
+    meshes: [OnceLock<ChunkMesh>; LOD_FACTORS.len() + 1],
+    /// Like the full mesh, but with large objects like trees replaced by coarse proxies made
+    /// from the same voxels. Built the first time it is asked for.
+    proxy: OnceLock<ChunkMesh>,
+    /// Box around every voxel and every level of detail, updated by `remesh`. `None` if the
+    /// chunk is empty.
+    pub bounds: Option<Aabb>,
+    /// Changes every time `remesh` is called, so renderers can tell when to upload the chunk
+    /// again. Unique across all chunks.
+    pub revision: u64,
     pub spawn_points: Vec<SpawnPoint>,
     pub objects: Vec<Object>,
+    /// The ground without any objects, in chunk coordinates. Used to fill in the ground again
+    /// when an object is removed.
+    pub ground: Vec<(IVec3, Material)>,
+    /// Voxels of objects in this chunk that reach into neighbouring chunks, in world
+    /// coordinates.
+    pub overflow: Vec<(IVec3, Material)>,
 }
 
 impl GenerationPositions {
-    fn new(
-        ground: Vec<InstanceData>,
-        spawn_points: Vec<SpawnPoint>,
-        objects: Vec<Object>,
-    ) -> GenerationPositions {
-        GenerationPositions {
-            ground,
-            spawn_points,
-            objects,
+    /// Instance data for every visible voxel, for drawing the chunk one cube at a time.
+    pub fn instances(&self) -> &[InstanceData] {
+        self.instances
+            .get_or_init(|| self.voxels.instances(self.origin))
+    }
+
+    /// Greedy mesh of the whole chunk at level of detail `lod`, where 0 is full detail and the
+    /// other levels are coarser by the factors in `LOD_FACTORS`.
+    pub fn mesh(&self, lod: usize) -> &ChunkMesh {
+        self.meshes[lod].get_or_init(|| lod_mesh(&self.voxels, self.origin, lod))
+    }
+
+    /// The greedy mesh at level of detail `lod`, or the full-detail one with large objects
+    /// replaced by proxies if `proxies` is set, if it has been built.
+    pub fn built_mesh(&self, lod: usize, proxies: bool) -> Option<&ChunkMesh> {
+        self.mesh_cell(lod, proxies).get()
+    }
+
+    /// Keeps `mesh` as the mesh `built_mesh` returns, unless one was built already.
+    pub(crate) fn set_mesh(&self, lod: usize, proxies: bool, mesh: ChunkMesh) {
+        let _ = self.mesh_cell(lod, proxies).set(mesh);
+    }
+
+    fn mesh_cell(&self, lod: usize, proxies: bool) -> &OnceLock<ChunkMesh> {
+        if proxies {
+            &self.proxy
+        } else {
+            &self.meshes[lod]
         }
     }
+
+    /// Updates everything derived from `voxels` after they changed. Instances and meshes are
+    /// built again when they are next asked for, so only what is drawn gets built.
+    pub fn remesh(&mut self) {
+        static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+        self.instances = OnceLock::new();
+        self.meshes = Default::default();
+        self.proxy = OnceLock::new();
+        let offset = IVec3::new(self.origin.x, 0, self.origin.y);
+        let positions = self.voxels.iter().map(|(p, _)| (p + offset).as_vec3());
+        self.bounds = Aabb::around_voxels(positions).map(|mut bounds| {
+            // Water moves up and down with the waves
+            if self.voxels.iter().any(|(_, material)| material.is_water) {
+                bounds.max.y += MAX_WAVE_HEIGHT;
+            }
+            // Coarse voxels are aligned to their size, so they can stick out above and
+            // below the real ones
+            let coarsest = LOD_FACTORS[LOD_FACTORS.len() - 1] as f32;
+            bounds.min.y -= coarsest - 1.0;
+            bounds.max.y += coarsest - 1.0;
+            bounds
+        });
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The voxels of the chunk at `origin` with the parts of `objects` inside it downsampled to
+/// blocks of `PROXY_FACTOR` voxels, if the objects have proxies. Voxels of the objects that
+/// have been edited or covered by something else are left alone. Blocks are aligned in world
+/// coordinates, so an object reaching into a neighbouring chunk gets the same blocks there.
+pub(crate) fn proxy_voxels<'a>(
+    voxels: &ChunkVoxels,
+    origin: IVec2,
+    objects: impl IntoIterator<Item = &'a Object>,
+) -> ChunkVoxels {
+    let offset = IVec3::new(origin.x, 0, origin.y);
+    let mut voxels = voxels.clone();
+    let mut replaced = Vec::new();
+    for object in objects.into_iter().filter(|object| object.has_proxy()) {
+        for (position, material) in object.models.iter().flat_map(model_voxels) {
+            let local = position - offset;
+            if voxels.get(local) == Some(material) {
+                voxels.set(local, None);
+                replaced.push((local, material));
+            }
+        }
+    }
+
+    let blocks = ChunkVoxels::from_voxels(voxels.width(), voxels.depth(), &replaced)
+        .downsample(PROXY_FACTOR);
+    for (block, material) in blocks.iter() {
+        for dy in 0..PROXY_FACTOR {
+            for dz in 0..PROXY_FACTOR {
+                for dx in 0..PROXY_FACTOR {
+                    let p = block * PROXY_FACTOR + IVec3::new(dx, dy, dz);
+                    // Don't bury the ground or anything else under the proxy
+                    if voxels.get(p).is_none() {
+                        voxels.set(p, Some(material));
+                    }
+                }
+            }
+        }
+    }
+    voxels
 }
 
 pub fn generate_terrain(
@@ -76,64 +427,53 @@ pub fn generate_terrain(
     config: &TerrainConfig,
     biome_config: &BiomeConfig,
 ) -> GenerationPositions {
-    let mut instance_data = Vec::new();
+    let origin = IVec2::new(x, z);
+    // Voxels in chunk coordinates, rasterized into the chunk's grid at the end
+    let mut voxels: Vec<(IVec3, Material)> = Vec::new();
     let mut spawn_points: Vec<SpawnPoint> = Vec::new();
     let depth = config.depth;
     let width = config.width;
-    let mut objects = Vec::new();
+    let mut objects: Vec<Object> = Vec::new();
 
     for z in z..z + depth {
         for x in x..x + width {
+            if !config.column_in_bounds(x, z) {
+                continue;
+            }
             let current_height = config.sample(x as f32, z as f32).trunc();
+            let local = |y: i32| IVec3::new(x - origin.x, y, z - origin.y);
+
+            if config.is_wall(x, z) {
+                let bottom = current_height.min(config.min_height) as i32;
+                let top = (config.max_height + WALL_CLEARANCE) as i32;
+                for y in bottom..=top {
+                    voxels.push((local(y), Material::new(WALL)));
+                }
+                continue;
+            }
 
             if current_height <= config.min_height {
-                let mut position = Vec3::new(x as f32, config.min_height, z as f32);
-                instance_data.push(InstanceData {
-                    position,
-                    color: WATER_BLUE,
-                    is_water: 1,
-                });
-                position.y += 1.;
-                instance_data.push(InstanceData {
-                    position,
-                    color: WATER_BLUE,
-                    is_water: 1,
-                });
+                let y = config.min_height as i32;
+                voxels.push((local(y), Material::water(WATER_BLUE)));
+                voxels.push((local(y + 1), Material::water(WATER_BLUE)));
                 continue;
             }
 
-            // Generate instance data for ground voxels
+            // Generate ground voxels
             let biome = biome_config.get_biome(x, z);
-            let color = match biome {
-                Biome::Forest | Biome::Field => Vec4::new(0.1, 0.5, 0.2, 1.0),
-                Biome::Desert => Vec4::new(0.7, 0.7, 0.1, 1.0),
-            };
+            let color = config.ground_color(&biome, x, z, current_height);
+            voxels.push((local(current_height as i32), Material::new(color)));
             let position = Vec3::new(x as f32, current_height, z as f32);
-            instance_data.push(InstanceData {
-                position,
-                color,
-                is_water: 0,
-            });
 
             // Biome_config will give some plant to spawn here or not depending on rng
             if let Some(spawn_type) = biome_config.get_spawn_type(x, z) {
-                let (color, object) = match spawn_type {
-                    SpawnType::Flower => (
-                        RED,
-                        Some(proc_gen_flower(
-                            0,
-                            Vec3::new(position.x, position.y + 1., position.z),
-                        )),
-                    ),
-                    SpawnType::Tree => (
-                        BROWN,
-                        Some(tree(0, Vec3::new(position.x, position.y + 1.0, position.z))),
-                    ),
-                    SpawnType::Cactus => (GREEN, None),
-                    SpawnType::Rock => (
-                        GREY,
-                        Some(rock(0, Vec3::new(position.x, position.y + 1., position.z))),
-                    ),
+                let base = Vec3::new(position.x, position.y + 1.0, position.z);
+                let seed = object_seed(x, z);
+                let (color, models) = match spawn_type {
+                    SpawnType::Flower => (RED, proc_gen_flower(seed, base)),
+                    SpawnType::Tree => (BROWN, tree(seed, base)),
+                    SpawnType::Cactus => (GREEN, cactus(seed, base)),
+                    SpawnType::Rock => (GREY, rock(seed, base)),
                 };
                 spawn_points.push(SpawnPoint::new(
                     InstanceData {
@@ -147,13 +487,58 @@ pub fn generate_terrain(
                     },
                     spawn_type,
                 ));
-                // TODO not option when we generate tree and cactus too
-                if let Some(object) = object {
-                    objects.push(object);
-                }
+                objects.push(Object {
+                    id: ((z - origin.y) * width + (x - origin.x)) as u64,
+                    models,
+                });
             }
         }
     }
-    GenerationPositions::new(instance_data, spawn_points, objects)
-    //GenerationPositions::new(instance_data, spawn_points)
+
+    // Rasterize the objects on top of the ground. Parts of objects that reach outside of the
+    // chunk are handed over to the neighbouring chunks.
+    let ground = voxels.clone();
+    let mut overflow = Vec::new();
+    let models = objects.iter().flat_map(|object| &object.models);
+    for (world_position, material) in models.flat_map(model_voxels) {
+        // Objects next to the wall of a finite world don't reach through it
+        if !config.column_in_bounds(world_position.x, world_position.z) {
+            continue;
+        }
+        let local = world_position - IVec3::new(origin.x, 0, origin.y);
+        if local.x >= 0 && local.x < width && local.z >= 0 && local.z < depth {
+            voxels.push((local, material));
+        } else {
+            overflow.push((world_position, material));
+        }
+    }
+
+    let voxels = ChunkVoxels::from_voxels(width, depth, &voxels);
+    let mut generated = GenerationPositions {
+        origin,
+        voxels,
+        instances: OnceLock::new(),
+        meshes: Default::default(),
+        proxy: OnceLock::new(),
+        bounds: None,
+        revision: 0,
+        spawn_points,
+        objects,
+        ground,
+        overflow,
+    };
+    generated.remesh();
+    generated
+}
+
+/// The voxels of a model in world coordinates, after applying its rotation and translation.
+pub fn model_voxels(model: &Model) -> impl Iterator<Item = (IVec3, Material)> + '_ {
+    model.points.iter().map(|point| {
+        let position = model.rotation * point.position + model.translation;
+        let material = Material {
+            color: point.color,
+            is_water: point.is_water != 0,
+        };
+        (voxel_at(position), material)
+    })
 }
@@ -1,12 +1,13 @@
 use glam::{Quat, Vec3};
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::models::primitives::{circle, sphere};
 use crate::models::Model;
 use crate::utils::{BROWN, GREEN};
 use crate::{InstanceData, Point};
 
-pub fn tree(_seed: u64, translation: Vec3) -> Vec<Model> {
+pub fn tree(seed: u64, translation: Vec3) -> Vec<Model> {
     let mut trunk = vec![];
     let tree_height = 40;
     for y in 0..tree_height {
@@ -26,7 +27,7 @@ pub fn tree(_seed: u64, translation: Vec3) -> Vec<Model> {
         translation,
     };
 
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed);
     let layers: i32 = 3;
     let mut canopy: Vec<Model> = vec![];
     for l in 0..layers {
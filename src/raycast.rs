@@ -0,0 +1,151 @@
+//! Finding the first voxel along a ray, for picking what the camera is looking at.
+//!
+//! This is the voxel traversal algorithm from "A Fast Voxel Traversal Algorithm for Ray
+//! Tracing" by Amanatides and Woo. The ray steps from voxel to voxel, always crossing the
+//! closest voxel boundary, so every voxel the ray passes through is visited exactly once.
+
+use glam::{IVec3, Vec3};
+
+use crate::chunks::{Chunks, ObjectId};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// The voxel that was hit.
+    pub voxel: IVec3,
+    /// Normal of the face of the voxel that was hit, in world space. Zero if the ray starts
+    /// inside the voxel.
+    pub normal: Vec3,
+    /// Distance along the ray to the face that was hit.
+    pub distance: f32,
+    /// The object the voxel belongs to, if any.
+    pub object: Option<ObjectId>,
+}
+
+/// Walks along the ray until `is_solid` returns true for a voxel or `max_distance` is reached.
+/// Voxels are centered on integer coordinates, like everywhere else.
+pub fn traverse(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    mut is_solid: impl FnMut(IVec3) -> bool,
+) -> Option<RayHit> {
+    let direction = direction.try_normalize()?;
+    // Shift by half a voxel so voxel boundaries are at integer coordinates
+    let start = origin + 0.5;
+    let mut voxel = start.floor().as_ivec3();
+    let step = IVec3::new(
+        if direction.x < 0.0 { -1 } else { 1 },
+        if direction.y < 0.0 { -1 } else { 1 },
+        if direction.z < 0.0 { -1 } else { 1 },
+    );
+    // How far along the ray we have to go to cross one voxel on each axis. Axes the ray is
+    // parallel to are never crossed.
+    let t_delta = direction.recip().abs();
+    let mut t_max = Vec3::ZERO;
+    for axis in 0..3 {
+        let boundary = if step[axis] > 0 {
+            voxel[axis] as f32 + 1.0
+        } else {
+            voxel[axis] as f32
+        };
+        t_max[axis] = if direction[axis] == 0.0 {
+            f32::INFINITY
+        } else {
+            (boundary - start[axis]) / direction[axis]
+        };
+    }
+
+    let mut normal = IVec3::ZERO;
+    let mut distance = 0.0;
+    loop {
+        if is_solid(voxel) {
+            return Some(RayHit {
+                voxel,
+                normal: normal.as_vec3(),
+                distance,
+                object: None,
+            });
+        }
+        let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+            0
+        } else if t_max.y < t_max.z {
+            1
+        } else {
+            2
+        };
+        distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+        voxel[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        normal = IVec3::ZERO;
+        normal[axis] = -step[axis];
+    }
+}
+
+impl Chunks {
+    /// Casts a ray against the loaded chunks. Water doesn't stop the ray, so it is possible to
+    /// pick the ground below it.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let hit = traverse(origin, direction, max_distance, |v| {
+            self.get_voxel(v).is_some_and(|m| !m.is_water)
+        })?;
+        Some(RayHit {
+            object: self.object_at(hit.voxel),
+            ..hit
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn hits_the_first_solid_voxel() {
+        let hit = traverse(Vec3::ZERO, Vec3::X, 100.0, |v| v.x >= 5).unwrap();
+        assert_eq!(hit.voxel, IVec3::new(5, 0, 0));
+        assert_eq!(hit.normal, Vec3::NEG_X);
+        assert_eq!(hit.distance, 4.5);
+
+        let hit = traverse(Vec3::ZERO, Vec3::NEG_Z, 100.0, |v| v.z <= -3).unwrap();
+        assert_eq!(hit.voxel, IVec3::new(0, 0, -3));
+        assert_eq!(hit.normal, Vec3::Z);
+        assert_eq!(hit.distance, 2.5);
+    }
+
+    #[test]
+    fn starting_inside_a_voxel_hits_it() {
+        let hit = traverse(Vec3::new(0.2, 0.3, -0.4), Vec3::Y, 10.0, |_| true).unwrap();
+        assert_eq!(hit.voxel, IVec3::ZERO);
+        assert_eq!(hit.normal, Vec3::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn stops_at_max_distance() {
+        assert!(traverse(Vec3::ZERO, Vec3::X, 5.0, |v| v.x == 10).is_none());
+        assert!(traverse(Vec3::ZERO, Vec3::ZERO, 5.0, |_| true).is_none());
+    }
+
+    #[test]
+    fn visits_neighbouring_voxels_one_step_at_a_time() {
+        let mut visited = Vec::new();
+        let direction = Vec3::new(0.7, -0.3, 0.45);
+        traverse(Vec3::new(0.1, 0.2, 0.3), direction, 30.0, |v| {
+            visited.push(v);
+            false
+        });
+        assert!(visited.len() > 30);
+        for pair in visited.windows(2) {
+            let step = pair[1] - pair[0];
+            assert_eq!(step.abs().element_sum(), 1, "{} to {}", pair[0], pair[1]);
+        }
+        // Every voxel along the way is visited once
+        let unique: HashSet<IVec3> = visited.iter().copied().collect();
+        assert_eq!(unique.len(), visited.len());
+    }
+}
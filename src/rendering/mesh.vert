@@ -0,0 +1,57 @@
+#version 330
+
+in  vec3 in_position;
+in  vec3 in_normal;
+in  vec4 in_color;
+in  vec3 in_voxel;
+in float in_ao;
+in uint is_water;
+
+flat out vec4 out_inst_color;
+flat out vec4 out_sun_color;
+out float out_ao;
+out vec4 shadow_position;
+out vec3 view_position;
+
+uniform mat4 proj_matrix;
+uniform mat4 model_matrix;
+uniform mat4 camera_matrix;
+uniform mat4 shadow_matrix;
+uniform vec3 sun_direction;
+uniform vec4 sun_color;
+uniform vec4 ambient_light_color;
+uniform float time;
+uniform float ambient_water_activity;
+uniform float wave_water_peak;
+uniform float wave_water_pow;
+uniform float wave_water_x_factor;
+uniform float wave_water_z_factor;
+uniform float wave_water_frequency;
+uniform float ambient_occlusion_strength;
+uniform sampler2D water_random;
+
+void main(void) {
+    vec3 pos = in_position;
+    if (is_water != uint(0)) {
+        // Same waves as in shader.vert, but moving every vertex of the voxel together
+        float x = in_voxel.x;
+        float z = in_voxel.z;
+        float random = texture(water_random, in_voxel.xz / 1024.0).r;
+        float amp = (sin((time + x*x * wave_water_x_factor + z*z * wave_water_z_factor) / wave_water_frequency) + 1.0) / 2.0;
+        pos.y += (pow(abs(amp), wave_water_pow) * wave_water_peak) + ambient_water_activity * (sin(time * (random * 3.0) + random*12.0) + 1.0) / 2.0;
+    }
+    gl_Position = proj_matrix * model_matrix * vec4(pos, 1.0);
+    shadow_position = shadow_matrix * model_matrix * vec4(pos, 1.0);
+    // Everything but the crosshair has the camera in the model matrix
+    view_position = (model_matrix * vec4(pos, 1.0)).xyz;
+
+    vec3 n = normalize(mat3(model_matrix) * in_normal);
+    vec3 s = normalize(mat3(camera_matrix)*sun_direction);
+    vec4 color_ambient = ambient_light_color * in_color;
+    vec4 color_sun = sun_color * max(0.0, dot(n, s)) * in_color;
+
+    // The sun is added in the fragment shader, where it is known if the fragment is in shadow
+    out_inst_color = color_ambient;
+    out_sun_color = color_sun;
+    out_ao = mix(1.0, in_ao, ambient_occlusion_strength);
+}
@@ -1,18 +1,32 @@
-use glam::{Mat4, Vec3, Vec4};
+use std::fmt;
+use std::str::FromStr;
+
+use glam::{IVec2, Mat4, Vec3, Vec4};
 use miniquad::{
-    Bindings, BufferLayout, BufferSource, BufferType, BufferUsage, Comparison, CullFace, GlContext,
-    Pipeline, PipelineParams, RenderingBackend as _, ShaderMeta, ShaderSource, TextureFormat,
-    TextureId, TextureKind, TextureParams, TextureWrap, UniformBlockLayout, UniformDesc,
-    UniformType, UniformsSource, VertexAttribute, VertexFormat, VertexStep,
+    Bindings, BufferId, BufferLayout, BufferSource, BufferType, BufferUsage, Comparison, CullFace,
+    GlContext, Pipeline, PipelineParams, RenderingBackend as _, ShaderMeta, ShaderSource,
+    TextureFormat, TextureId, TextureKind, TextureParams, TextureWrap, UniformBlockLayout,
+    UniformDesc, UniformType, UniformsSource, VertexAttribute, VertexFormat, VertexStep,
 };
 use rand::{thread_rng, Rng as _};
 
-use crate::utils::now_f32;
+use voxel_garden::utils::now_f32;
+use voxel_garden::{Chunks, InstanceData};
+
+use self::shadows::{ShadowMap, ShadowUniforms};
+use self::sky::Sky;
+pub use self::sky::SkyParams;
+
+mod shadows;
+mod sky;
 
 const VERTEX_SHADER: &str = include_str!("shader.vert");
+const MESH_VERTEX_SHADER: &str = include_str!("mesh.vert");
 const FRAGMENT_SHADER: &str = include_str!("shader.frag");
 
-const MAX_INSTANCE_DATA: usize = std::mem::size_of::<InstanceData>() * 100_000;
+/// How many instances fit in the shared instance buffer used by `draw_voxels`. Larger slices
+/// are drawn in batches.
+const MAX_INSTANCES: usize = 100_000;
 pub struct Shader {
     /// A pipeline (rendering pipeline) collects information that is applied before draw
     /// calls. It contains:
@@ -25,16 +39,194 @@ pub struct Shader {
     bindings: Bindings,
     /// Amount of vertices in the cube.
     cube_vertices: i32,
+    /// Pipeline for drawing chunk meshes instead of cube instances.
+    mesh_pipeline: Pipeline,
+    /// Texture used for the water waves.
+    water_random_tex: TextureId,
+    shadow_map: ShadowMap,
+    sky: Sky,
+    /// Bytes uploaded to the GPU since the last call to `take_uploaded_bytes`.
+    uploaded_bytes: usize,
+    /// Draw calls made since the last call to `take_draw_calls`.
+    draw_calls: usize,
+    /// Calls to `draw_voxels` since the last call to `take_split_draws` that had to be split
+    /// into several batches.
+    split_draws: usize,
+    /// Whether a warning about splitting has been printed. It is only printed once.
+    warned_split: bool,
 
+    /// Direction towards the sun, or the moon at night.
     sun_direction: Vec3,
     sun_color: Vec4,
     ambient_light_color: Vec4,
-    ambient_water_activity: f32,
-    wave_water_peak: f32,
-    wave_water_pow: f32,
-    wave_water_x_factor: f32,
-    wave_water_z_factor: f32,
-    wave_water_frequency: f32,
+    fog: Option<Fog>,
+    pub settings: ShaderSettings,
+}
+
+/// How chunks are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    /// One cube instance per visible voxel.
+    Instanced,
+    /// A greedy mesh per chunk.
+    Greedy,
+}
+
+impl FromStr for RenderPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instanced" => Ok(RenderPath::Instanced),
+            "greedy" => Ok(RenderPath::Greedy),
+            _ => Err(format!(
+                "invalid render path {s:?}, expected greedy or instanced"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RenderPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RenderPath::Instanced => "instanced",
+            RenderPath::Greedy => "greedy",
+        })
+    }
+}
+
+/// The GPU buffers of a chunk, owned by the chunk until it is deleted. They are made for one
+/// render path and can only be drawn with that path.
+pub enum ChunkBuffers {
+    Instanced {
+        instance_buffer: BufferId,
+        instances: i32,
+    },
+    Greedy {
+        vertex_buffer: BufferId,
+        index_buffer: BufferId,
+        indices: i32,
+    },
+}
+
+impl ChunkBuffers {
+    pub fn delete(self, ctx: &mut GlContext) {
+        match self {
+            ChunkBuffers::Instanced {
+                instance_buffer, ..
+            } => ctx.delete_buffer(instance_buffer),
+            ChunkBuffers::Greedy {
+                vertex_buffer,
+                index_buffer,
+                ..
+            } => {
+                ctx.delete_buffer(vertex_buffer);
+                ctx.delete_buffer(index_buffer);
+            }
+        }
+    }
+
+    pub fn triangles(&self) -> usize {
+        match self {
+            // A cube is 12 triangles
+            ChunkBuffers::Instanced { instances, .. } => *instances as usize * 12,
+            ChunkBuffers::Greedy { indices, .. } => *indices as usize / 3,
+        }
+    }
+}
+
+/// How distance fog thickens between where it starts and ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FogMode {
+    Off,
+    Linear,
+    Exponential,
+}
+
+impl FromStr for FogMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(FogMode::Off),
+            "linear" => Ok(FogMode::Linear),
+            "exponential" => Ok(FogMode::Exponential),
+            _ => Err(format!(
+                "invalid fog mode {s:?}, expected off, linear or exponential"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FogMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FogMode::Off => "off",
+            FogMode::Linear => "linear",
+            FogMode::Exponential => "exponential",
+        })
+    }
+}
+
+/// Fog for the current frame. Distances are in voxels from the camera.
+#[derive(Clone, Copy, Debug)]
+pub struct Fog {
+    pub color: Vec3,
+    pub start: f32,
+    pub end: f32,
+    pub exponential: bool,
+}
+
+/// Uniforms that can be changed in the Debug window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShaderSettings {
+    /// Colour of the sun during the day. The world clock decides the colour at other times.
+    pub sun_color: Vec4,
+    /// Colour of the ambient light during the day.
+    pub ambient_light_color: Vec4,
+    pub ambient_water_activity: f32,
+    pub wave_water_peak: f32,
+    pub wave_water_pow: f32,
+    pub wave_water_x_factor: f32,
+    pub wave_water_z_factor: f32,
+    pub wave_water_frequency: f32,
+    /// Darkening of corners and creases in chunk meshes. Cube instances have no occlusion.
+    pub ambient_occlusion: bool,
+    pub ambient_occlusion_strength: f32,
+    pub shadows: bool,
+    /// Width and height of the shadow map in texels.
+    pub shadow_map_size: u32,
+    /// How much closer to the sun than the shadow map a fragment has to be to count as lit,
+    /// in shadow map depth. Too low gives stripes of shadow on lit faces (shadow acne).
+    pub shadow_bias: f32,
+    /// How far from the camera shadows reach, in voxels.
+    pub shadow_distance: f32,
+    pub fog: FogMode,
+    /// Where the fog starts, as a fraction of the distance where it is thickest.
+    pub fog_start: f32,
+}
+
+impl Default for ShaderSettings {
+    fn default() -> Self {
+        ShaderSettings {
+            sun_color: Vec4::new(1.0, 1.0, 0.2, 1.0),
+            ambient_light_color: Vec4::new(0.7, 0.7, 0.7, 1.0),
+            ambient_water_activity: 0.25,
+            wave_water_peak: 0.7,
+            wave_water_pow: 8.0,
+            wave_water_x_factor: 0.0005,
+            wave_water_z_factor: 0.00115,
+            wave_water_frequency: 3.0,
+            ambient_occlusion: true,
+            ambient_occlusion_strength: 0.6,
+            shadows: true,
+            shadow_map_size: 2048,
+            shadow_bias: 0.0005,
+            shadow_distance: 128.0,
+            fog: FogMode::Linear,
+            fog_start: 0.6,
+        }
+    }
 }
 
 impl Shader {
@@ -100,17 +292,48 @@ impl Shader {
         let instance_buffer = ctx.new_buffer(
             BufferType::VertexBuffer,
             BufferUsage::Stream, // TODO: dynamic?
-            BufferSource::empty::<InstanceData>(MAX_INSTANCE_DATA),
+            BufferSource::empty::<InstanceData>(MAX_INSTANCES),
         );
 
         let water_random_tex = generate_random_texture(ctx, 1024, 1024);
 
+        let settings = ShaderSettings::default();
+        let shadow_map = ShadowMap::new(ctx, settings.shadow_map_size);
+
         let bindings = Bindings {
             vertex_buffers: vec![cube_vertex_buffer, instance_buffer],
             index_buffer: cube_index_buffer,
-            images: vec![water_random_tex],
+            images: vec![water_random_tex, shadow_map.depth_texture()],
         };
 
+        let mesh_shader = ctx
+            .new_shader(
+                ShaderSource::Glsl {
+                    vertex: MESH_VERTEX_SHADER,
+                    fragment: FRAGMENT_SHADER,
+                },
+                meta(),
+            )
+            .unwrap();
+        let mesh_pipeline = ctx.new_pipeline(
+            &[BufferLayout::default()],
+            &[
+                VertexAttribute::new("in_position", VertexFormat::Float3),
+                VertexAttribute::new("in_normal", VertexFormat::Float3),
+                VertexAttribute::new("in_color", VertexFormat::Float4),
+                VertexAttribute::new("in_voxel", VertexFormat::Float3),
+                VertexAttribute::new("in_ao", VertexFormat::Float1),
+                VertexAttribute::new("is_water", VertexFormat::Int1),
+            ],
+            mesh_shader,
+            PipelineParams {
+                depth_test: Comparison::Less,
+                depth_write: true,
+                cull_face: CullFace::Back,
+                ..Default::default()
+            },
+        );
+
         let pipeline = ctx.new_pipeline(
             &[
                 // buffer 0: geometry vertex buffer
@@ -141,39 +364,259 @@ impl Shader {
             pipeline,
             bindings,
             cube_vertices: indices.len() as i32,
+            mesh_pipeline,
+            water_random_tex,
+            shadow_map,
+            sky: Sky::new(ctx),
+            uploaded_bytes: 0,
+            draw_calls: 0,
+            split_draws: 0,
+            warned_split: false,
 
             sun_direction: Vec3::new(1.0, 1.0, 0.0),
-            sun_color: Vec4::new(1.0, 1.0, 0.2, 1.0),
-            ambient_light_color: Vec4::new(0.7, 0.7, 0.7, 1.0),
-            ambient_water_activity: 0.25,
-            wave_water_peak: 0.7,
-            wave_water_pow: 8.0,
-            wave_water_x_factor: 0.0005,
-            wave_water_z_factor: 0.00115,
-            wave_water_frequency: 3.0,
+            sun_color: settings.sun_color,
+            ambient_light_color: settings.ambient_light_color,
+            fog: None,
+            settings,
         }
     }
 
-    pub fn prepare_draw(&self, ctx: &mut GlContext) {
+    /// Sets where the light comes from and its colours, for the time of day.
+    pub fn set_light(&mut self, direction: Vec3, color: Vec4, ambient_color: Vec4) {
+        self.sun_direction = direction;
+        self.sun_color = color;
+        self.ambient_light_color = ambient_color;
+    }
+
+    /// Sets the fog for the frame, or turns it off with `None`.
+    pub fn set_fog(&mut self, fog: Option<Fog>) {
+        self.fog = fog;
+    }
+
+    /// Draws the sky over the whole screen. Draw it first, everything else covers it.
+    pub fn draw_sky(
+        &mut self,
+        ctx: &mut GlContext,
+        proj_matrix: Mat4,
+        camera_matrix: Mat4,
+        params: &SkyParams,
+    ) {
+        self.sky.draw(ctx, proj_matrix, camera_matrix, params);
+        self.draw_calls += 1;
+    }
+
+    /// Applies the pipeline and bindings used by `draw_voxels`. Needs to be called again after
+    /// drawing chunks.
+    pub fn prepare_draw(&mut self, ctx: &mut GlContext) {
+        self.bindings.images = self.images();
         ctx.apply_pipeline(&self.pipeline);
         ctx.apply_bindings(&self.bindings);
     }
 
+    /// Textures used by the voxel shaders.
+    fn images(&self) -> Vec<TextureId> {
+        vec![self.water_random_tex, self.shadow_map.depth_texture()]
+    }
+
+    /// Moves the shadow map so it is centered on `center`, and resizes it if the size has
+    /// changed.
+    pub fn update_shadow_map(&mut self, ctx: &mut GlContext, center: Vec3) {
+        self.shadow_map.resize(ctx, self.settings.shadow_map_size);
+        self.shadow_map
+            .follow(center, self.sun_direction, self.settings.shadow_distance);
+    }
+
+    /// Starts drawing into the shadow map with `draw_chunk_shadow`. End with
+    /// `end_render_pass` before drawing anything else.
+    pub fn begin_shadow_pass(&self, ctx: &mut GlContext) {
+        self.shadow_map.begin_pass(ctx);
+    }
+
+    /// The view-projection matrix of the sun, for finding which chunks can cast shadows.
+    pub fn light_matrix(&self) -> Mat4 {
+        self.shadow_map.light_matrix()
+    }
+
+    /// Draws a chunk into the shadow map.
+    pub fn draw_chunk_shadow(&mut self, ctx: &mut GlContext, buffers: &ChunkBuffers) {
+        let pipeline = match buffers {
+            ChunkBuffers::Instanced { .. } => &self.shadow_map.instanced_pipeline,
+            ChunkBuffers::Greedy { .. } => &self.shadow_map.mesh_pipeline,
+        };
+        ctx.apply_pipeline(pipeline);
+        let (mut bindings, elements, instances) = self.chunk_bindings(buffers);
+        bindings.images.clear();
+        ctx.apply_bindings(&bindings);
+        ctx.apply_uniforms(UniformsSource::table(&ShadowUniforms {
+            light_matrix: self.shadow_map.light_matrix(),
+        }));
+        ctx.draw(0, elements, instances);
+        self.draw_calls += 1;
+    }
+
+    /// Uploads the instances or mesh of `chunk`, depending on `path`. `lod` selects the
+    /// level of detail of the mesh, where 0 is the full chunk and higher levels are
+    /// coarser. At level 0, `proxies` selects the mesh where large objects are replaced
+    /// by proxies. Instances only have the full level. Empty chunks aren't uploaded at all.
+    pub fn upload_chunk(
+        &mut self,
+        ctx: &mut GlContext,
+        path: RenderPath,
+        terrain: &Chunks,
+        chunk: IVec2,
+        lod: usize,
+        proxies: bool,
+    ) -> Option<ChunkBuffers> {
+        let data = terrain.get(&chunk)?;
+        match path {
+            RenderPath::Instanced => {
+                let instances = data.instances();
+                if instances.is_empty() {
+                    return None;
+                }
+                let instance_buffer = ctx.new_buffer(
+                    BufferType::VertexBuffer,
+                    BufferUsage::Immutable,
+                    BufferSource::slice(instances),
+                );
+                self.uploaded_bytes += std::mem::size_of_val(instances);
+                Some(ChunkBuffers::Instanced {
+                    instance_buffer,
+                    instances: instances.len() as i32,
+                })
+            }
+            RenderPath::Greedy => {
+                let mesh = data.built_mesh(lod, proxies)?;
+                if mesh.indices.is_empty() {
+                    return None;
+                }
+                let vertex_buffer = ctx.new_buffer(
+                    BufferType::VertexBuffer,
+                    BufferUsage::Immutable,
+                    BufferSource::slice(&mesh.vertices),
+                );
+                let index_buffer = ctx.new_buffer(
+                    BufferType::IndexBuffer,
+                    BufferUsage::Immutable,
+                    BufferSource::slice(&mesh.indices),
+                );
+                self.uploaded_bytes += std::mem::size_of_val(&mesh.vertices[..])
+                    + std::mem::size_of_val(&mesh.indices[..]);
+                Some(ChunkBuffers::Greedy {
+                    vertex_buffer,
+                    index_buffer,
+                    indices: mesh.indices.len() as i32,
+                })
+            }
+        }
+    }
+
+    /// Applies the pipeline used by `draw_chunk` for buffers made for `path`.
+    pub fn prepare_chunk_draw(&self, ctx: &mut GlContext, path: RenderPath) {
+        match path {
+            RenderPath::Instanced => ctx.apply_pipeline(&self.pipeline),
+            RenderPath::Greedy => ctx.apply_pipeline(&self.mesh_pipeline),
+        }
+    }
+
+    /// Draws a chunk from its own buffers. Nothing is uploaded.
+    pub fn draw_chunk(
+        &mut self,
+        ctx: &mut GlContext,
+        buffers: &ChunkBuffers,
+        proj_matrix: Mat4,
+        model_matrix: Mat4,
+        camera_matrix: Mat4,
+    ) {
+        let (bindings, elements, instances) = self.chunk_bindings(buffers);
+        ctx.apply_bindings(&bindings);
+        ctx.apply_uniforms(UniformsSource::table(&self.uniforms(
+            proj_matrix,
+            model_matrix,
+            camera_matrix,
+        )));
+        ctx.draw(0, elements, instances);
+        self.draw_calls += 1;
+    }
+
+    /// Bindings for drawing a chunk, with the number of elements and instances to draw.
+    fn chunk_bindings(&self, buffers: &ChunkBuffers) -> (Bindings, i32, i32) {
+        match *buffers {
+            ChunkBuffers::Instanced {
+                instance_buffer,
+                instances,
+            } => (
+                Bindings {
+                    vertex_buffers: vec![self.bindings.vertex_buffers[0], instance_buffer],
+                    index_buffer: self.bindings.index_buffer,
+                    images: self.images(),
+                },
+                self.cube_vertices,
+                instances,
+            ),
+            ChunkBuffers::Greedy {
+                vertex_buffer,
+                index_buffer,
+                indices,
+            } => (
+                Bindings {
+                    vertex_buffers: vec![vertex_buffer],
+                    index_buffer,
+                    images: self.images(),
+                },
+                indices,
+                1,
+            ),
+        }
+    }
+
+    /// How many bytes have been uploaded to the GPU since the last call.
+    pub fn take_uploaded_bytes(&mut self) -> usize {
+        std::mem::take(&mut self.uploaded_bytes)
+    }
+
+    /// How many draw calls have been made since the last call.
+    pub fn take_draw_calls(&mut self) -> usize {
+        std::mem::take(&mut self.draw_calls)
+    }
+
+    /// How many calls to `draw_voxels` had to be split into batches since the last call.
+    pub fn take_split_draws(&mut self) -> usize {
+        std::mem::take(&mut self.split_draws)
+    }
+
+    /// Draws instances that change often, like the highlight, by uploading them to a shared
+    /// buffer. Chunks use `draw_chunk` instead. More than `MAX_INSTANCES` instances are drawn
+    /// in several batches.
     pub fn draw_voxels(
-        &self,
+        &mut self,
         ctx: &mut GlContext,
         data: &[InstanceData],
         proj_matrix: Mat4,
         model_matrix: Mat4,
         camera_matrix: Mat4,
     ) {
-        ctx.buffer_update(self.bindings.vertex_buffers[1], BufferSource::slice(data));
+        if data.len() > MAX_INSTANCES {
+            if cfg!(debug_assertions) && !self.warned_split {
+                self.warned_split = true;
+                eprintln!(
+                    "warning: drawing {} instances in batches of {MAX_INSTANCES}",
+                    data.len()
+                );
+            }
+            self.split_draws += 1;
+        }
         ctx.apply_uniforms(UniformsSource::table(&self.uniforms(
             proj_matrix,
             model_matrix,
             camera_matrix,
         )));
-        ctx.draw(0, self.cube_vertices, data.len() as i32);
+        for batch in data.chunks(MAX_INSTANCES) {
+            ctx.buffer_update(self.bindings.vertex_buffers[1], BufferSource::slice(batch));
+            self.uploaded_bytes += std::mem::size_of_val(batch);
+            ctx.draw(0, self.cube_vertices, batch.len() as i32);
+            self.draw_calls += 1;
+        }
     }
 
     #[cfg(feature = "egui")]
@@ -182,41 +625,50 @@ impl Shader {
 
         ui.label("ambient light color");
         let mut rgb = [
-            self.ambient_light_color.x,
-            self.ambient_light_color.y,
-            self.ambient_light_color.z,
+            self.settings.ambient_light_color.x,
+            self.settings.ambient_light_color.y,
+            self.settings.ambient_light_color.z,
         ];
         color_edit_button_rgb(ui, &mut rgb);
-        self.ambient_light_color.x = rgb[0];
-        self.ambient_light_color.y = rgb[1];
-        self.ambient_light_color.z = rgb[2];
+        self.settings.ambient_light_color.x = rgb[0];
+        self.settings.ambient_light_color.y = rgb[1];
+        self.settings.ambient_light_color.z = rgb[2];
         ui.end_row();
 
         ui.label("sun color");
-        let mut rgb = [self.sun_color.x, self.sun_color.y, self.sun_color.z];
+        let mut rgb = [
+            self.settings.sun_color.x,
+            self.settings.sun_color.y,
+            self.settings.sun_color.z,
+        ];
         color_edit_button_rgb(ui, &mut rgb);
-        self.sun_color.x = rgb[0];
-        self.sun_color.y = rgb[1];
-        self.sun_color.z = rgb[2];
+        self.settings.sun_color.x = rgb[0];
+        self.settings.sun_color.y = rgb[1];
+        self.settings.sun_color.z = rgb[2];
         ui.end_row();
 
         ui.label("ambient water activity");
         ui.add(
-            egui::Slider::new(&mut self.ambient_water_activity, (0.0)..=1.0).clamp_to_range(true),
+            egui::Slider::new(&mut self.settings.ambient_water_activity, (0.0)..=1.0)
+                .clamp_to_range(true),
         );
         ui.end_row();
 
         ui.label("wave water peak");
-        ui.add(egui::Slider::new(&mut self.wave_water_peak, (0.0)..=1.0).clamp_to_range(true));
+        ui.add(
+            egui::Slider::new(&mut self.settings.wave_water_peak, (0.0)..=1.0).clamp_to_range(true),
+        );
         ui.end_row();
 
         ui.label("wave water pow");
-        ui.add(egui::Slider::new(&mut self.wave_water_pow, (0.0)..=20.0).clamp_to_range(true));
+        ui.add(
+            egui::Slider::new(&mut self.settings.wave_water_pow, (0.0)..=20.0).clamp_to_range(true),
+        );
         ui.end_row();
 
         ui.label("wave water x factor");
         ui.add(
-            egui::Slider::new(&mut self.wave_water_x_factor, (0.0)..=0.01)
+            egui::Slider::new(&mut self.settings.wave_water_x_factor, (0.0)..=0.01)
                 .clamp_to_range(true)
                 .logarithmic(true),
         );
@@ -224,7 +676,7 @@ impl Shader {
 
         ui.label("wave water z factor");
         ui.add(
-            egui::Slider::new(&mut self.wave_water_z_factor, (0.0)..=0.01)
+            egui::Slider::new(&mut self.settings.wave_water_z_factor, (0.0)..=0.01)
                 .clamp_to_range(true)
                 .logarithmic(true),
         );
@@ -232,7 +684,74 @@ impl Shader {
 
         ui.label("wave water frequency");
         ui.add(
-            egui::Slider::new(&mut self.wave_water_frequency, (0.0)..=20.0).clamp_to_range(true),
+            egui::Slider::new(&mut self.settings.wave_water_frequency, (0.0)..=20.0)
+                .clamp_to_range(true),
+        );
+        ui.end_row();
+
+        ui.label("ambient occlusion");
+        ui.checkbox(&mut self.settings.ambient_occlusion, "");
+        ui.end_row();
+
+        ui.label("ambient occlusion strength");
+        ui.add_enabled(
+            self.settings.ambient_occlusion,
+            egui::Slider::new(&mut self.settings.ambient_occlusion_strength, (0.0)..=1.0)
+                .clamp_to_range(true),
+        );
+        ui.end_row();
+
+        ui.label("shadows");
+        ui.checkbox(&mut self.settings.shadows, "");
+        ui.end_row();
+
+        ui.label("shadow map size");
+        ui.add_enabled_ui(self.settings.shadows, |ui| {
+            egui::ComboBox::from_id_source("shadow map size")
+                .selected_text(self.settings.shadow_map_size.to_string())
+                .show_ui(ui, |ui| {
+                    for size in [512, 1024, 2048, 4096, 8192] {
+                        ui.selectable_value(
+                            &mut self.settings.shadow_map_size,
+                            size,
+                            size.to_string(),
+                        );
+                    }
+                });
+        });
+        ui.end_row();
+
+        ui.label("shadow bias");
+        ui.add_enabled(
+            self.settings.shadows,
+            egui::Slider::new(&mut self.settings.shadow_bias, (0.00001)..=0.01)
+                .clamp_to_range(true)
+                .logarithmic(true),
+        );
+        ui.end_row();
+
+        ui.label("shadow distance");
+        ui.add_enabled(
+            self.settings.shadows,
+            egui::Slider::new(&mut self.settings.shadow_distance, (16.0)..=512.0)
+                .clamp_to_range(true),
+        );
+        ui.end_row();
+
+        ui.label("fog");
+        egui::ComboBox::from_id_source("fog")
+            .selected_text(self.settings.fog.to_string())
+            .show_ui(ui, |ui| {
+                for mode in [FogMode::Off, FogMode::Linear, FogMode::Exponential] {
+                    ui.selectable_value(&mut self.settings.fog, mode, mode.to_string());
+                }
+            });
+        ui.end_row();
+
+        ui.label("fog start");
+        ui.add_enabled(
+            self.settings.fog != FogMode::Off,
+            egui::Slider::new(&mut self.settings.fog_start, (0.0)..=1.0).clamp_to_range(true),
         );
         ui.end_row();
     }
@@ -242,16 +761,34 @@ impl Shader {
             proj_matrix,
             model_matrix,
             camera_matrix,
+            // Chunks are drawn with the camera in the model matrix, so it is undone to get back
+            // to world coordinates
+            shadow_matrix: self.shadow_map.light_matrix() * camera_matrix.inverse(),
             time: now_f32(),
             sun_direction: self.sun_direction,
             sun_color: self.sun_color,
             ambient_light_color: self.ambient_light_color,
-            ambient_water_activity: self.ambient_water_activity,
-            wave_water_peak: self.wave_water_peak,
-            wave_water_pow: self.wave_water_pow,
-            wave_water_x_factor: self.wave_water_x_factor,
-            wave_water_z_factor: self.wave_water_z_factor,
-            wave_water_frequency: self.wave_water_frequency,
+            ambient_water_activity: self.settings.ambient_water_activity,
+            wave_water_peak: self.settings.wave_water_peak,
+            wave_water_pow: self.settings.wave_water_pow,
+            wave_water_x_factor: self.settings.wave_water_x_factor,
+            wave_water_z_factor: self.settings.wave_water_z_factor,
+            wave_water_frequency: self.settings.wave_water_frequency,
+            ambient_occlusion_strength: if self.settings.ambient_occlusion {
+                self.settings.ambient_occlusion_strength
+            } else {
+                0.0
+            },
+            shadow_strength: if self.settings.shadows { 1.0 } else { 0.0 },
+            shadow_bias: self.settings.shadow_bias,
+            fog_color: self.fog.map_or(Vec3::ZERO, |fog| fog.color),
+            fog_start: self.fog.map_or(0.0, |fog| fog.start),
+            fog_end: self.fog.map_or(0.0, |fog| fog.end),
+            fog_exponential: match self.fog {
+                Some(fog) if fog.exponential => 1.0,
+                _ => 0.0,
+            },
+            fog_strength: if self.fog.is_some() { 1.0 } else { 0.0 },
         }
     }
 }
@@ -285,24 +822,16 @@ struct VertexData {
     normal: Vec3,
 }
 
-/// Uploaded instance data to the GPU
-#[repr(C)]
-#[derive(Clone, Copy)]
-pub struct InstanceData {
-    pub position: Vec3,
-    pub color: Vec4,
-    pub is_water: u32,
-}
-
 pub fn meta() -> ShaderMeta {
     ShaderMeta {
-        images: vec!["water_random".to_string()],
+        images: vec!["water_random".to_string(), "shadow_map".to_string()],
         uniforms: UniformBlockLayout {
             // The order here needs to match the order in the Uniforms struct
             uniforms: vec![
                 UniformDesc::new("proj_matrix", UniformType::Mat4),
                 UniformDesc::new("model_matrix", UniformType::Mat4),
                 UniformDesc::new("camera_matrix", UniformType::Mat4),
+                UniformDesc::new("shadow_matrix", UniformType::Mat4),
                 UniformDesc::new("sun_direction", UniformType::Float3),
                 UniformDesc::new("time", UniformType::Float1),
                 UniformDesc::new("sun_color", UniformType::Float4),
@@ -313,6 +842,14 @@ pub fn meta() -> ShaderMeta {
                 UniformDesc::new("wave_water_x_factor", UniformType::Float1),
                 UniformDesc::new("wave_water_z_factor", UniformType::Float1),
                 UniformDesc::new("wave_water_frequency", UniformType::Float1),
+                UniformDesc::new("ambient_occlusion_strength", UniformType::Float1),
+                UniformDesc::new("shadow_strength", UniformType::Float1),
+                UniformDesc::new("shadow_bias", UniformType::Float1),
+                UniformDesc::new("fog_color", UniformType::Float3),
+                UniformDesc::new("fog_start", UniformType::Float1),
+                UniformDesc::new("fog_end", UniformType::Float1),
+                UniformDesc::new("fog_exponential", UniformType::Float1),
+                UniformDesc::new("fog_strength", UniformType::Float1),
             ],
         },
     }
@@ -323,6 +860,7 @@ pub struct Uniforms {
     pub proj_matrix: Mat4,
     pub model_matrix: Mat4,
     pub camera_matrix: Mat4,
+    pub shadow_matrix: Mat4,
     pub sun_direction: Vec3,
     pub time: f32,
     pub sun_color: Vec4,
@@ -333,4 +871,12 @@ pub struct Uniforms {
     pub wave_water_x_factor: f32,
     pub wave_water_z_factor: f32,
     pub wave_water_frequency: f32,
+    pub ambient_occlusion_strength: f32,
+    pub shadow_strength: f32,
+    pub shadow_bias: f32,
+    pub fog_color: Vec3,
+    pub fog_start: f32,
+    pub fog_end: f32,
+    pub fog_exponential: f32,
+    pub fog_strength: f32,
 }
@@ -1,7 +1,51 @@
 #version 330
 
 flat in vec4 out_inst_color;
+flat in vec4 out_sun_color;
+in float out_ao;
+in vec4 shadow_position;
+in vec3 view_position;
+
+uniform sampler2D shadow_map;
+uniform float shadow_strength;
+uniform float shadow_bias;
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_exponential;
+uniform float fog_strength;
+
+// How much of the sun reaches the fragment, averaged over a 3x3 area of the shadow map so the
+// edges of shadows are soft (percentage-closer filtering)
+float sun_visibility() {
+    if (shadow_strength == 0.0) {
+        return 1.0;
+    }
+    vec3 p = shadow_position.xyz / shadow_position.w * 0.5 + 0.5;
+    // Outside of the shadow map nothing is known, so everything is lit
+    if (any(lessThan(p, vec3(0.0))) || any(greaterThan(p, vec3(1.0)))) {
+        return 1.0;
+    }
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0));
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            float depth = texture(shadow_map, p.xy + vec2(x, y) * texel).r;
+            lit += p.z - shadow_bias <= depth ? 1.0 : 0.0;
+        }
+    }
+    return mix(1.0, lit / 9.0, shadow_strength);
+}
+
+// How much of the fragment is hidden by fog, from 0 at fog_start to 1 at fog_end
+float fog() {
+    float t = max(length(view_position) - fog_start, 0.0) / max(fog_end - fog_start, 0.001);
+    // Exponential fog is 99% thick at fog_end
+    float amount = fog_exponential != 0.0 ? 1.0 - exp(-4.6 * t) : t;
+    return clamp(amount, 0.0, 1.0) * fog_strength;
+}
 
 void main(void) {
-    gl_FragColor = out_inst_color;
+    vec4 color = out_inst_color + out_sun_color * sun_visibility();
+    gl_FragColor = vec4(mix(color.rgb * out_ao, fog_color, fog()), color.a);
 }
@@ -7,10 +7,15 @@ in  vec4 in_inst_color;
 in uint is_water;
 
 flat out vec4 out_inst_color;
+flat out vec4 out_sun_color;
+out float out_ao;
+out vec4 shadow_position;
+out vec3 view_position;
 
 uniform mat4 proj_matrix;
 uniform mat4 model_matrix;
 uniform mat4 camera_matrix;
+uniform mat4 shadow_matrix;
 uniform vec3 sun_direction;
 uniform vec4 sun_color;
 uniform vec4 ambient_light_color;
@@ -34,11 +39,17 @@ void main(void) {
     }
     vec3 pos = new_inst_pos + in_position;
     gl_Position = proj_matrix * model_matrix * vec4(pos, 1.0);
+    shadow_position = shadow_matrix * model_matrix * vec4(pos, 1.0);
+    // Everything but the crosshair has the camera in the model matrix
+    view_position = (model_matrix * vec4(pos, 1.0)).xyz;
 
     vec3 n = normalize(mat3(model_matrix) * in_normal);
     vec3 s = normalize(mat3(camera_matrix)*sun_direction);
     vec4 color_ambient = ambient_light_color * in_inst_color;
     vec4 color_sun = sun_color * max(0.0, dot(n, s)) * in_inst_color;
 
-    out_inst_color = color_ambient + color_sun;
+    // The sun is added in the fragment shader, where it is known if the fragment is in shadow
+    out_inst_color = color_ambient;
+    out_sun_color = color_sun;
+    out_ao = 1.0;
 }
@@ -0,0 +1,15 @@
+#version 330
+
+in vec3 in_position;
+in uint is_water;
+
+uniform mat4 light_matrix;
+
+void main(void) {
+    // Water lets the light through, so it is moved outside of the shadow map
+    if (is_water != uint(0)) {
+        gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        return;
+    }
+    gl_Position = light_matrix * vec4(in_position, 1.0);
+}
@@ -0,0 +1,200 @@
+//! Shadows from the sun.
+//!
+//! Before the chunks are drawn, the ones that can cast shadows into view are drawn once more
+//! from the sun's point of view into a depth texture, the shadow map. When drawing normally, a
+//! fragment is in shadow if something was closer to the sun in the shadow map. The shadow map
+//! covers a square around the camera and follows it, so one map is enough for the whole render
+//! distance at the cost of resolution.
+
+use glam::{Mat4, Vec3};
+use miniquad::{
+    BufferLayout, Comparison, CullFace, FilterMode, GlContext, PassAction, Pipeline,
+    PipelineParams, RenderPass, RenderingBackend as _, ShaderMeta, ShaderSource, TextureFormat,
+    TextureId, TextureParams, TextureWrap, UniformBlockLayout, UniformDesc, UniformType,
+    VertexAttribute, VertexFormat, VertexStep,
+};
+
+const VERTEX_SHADER: &str = include_str!("shadow.vert");
+const MESH_VERTEX_SHADER: &str = include_str!("shadow_mesh.vert");
+const FRAGMENT_SHADER: &str = include_str!("shadow.frag");
+
+/// How far above and below the area around the camera things can cast shadows into it.
+const CASTER_DEPTH: f32 = 256.0;
+
+pub struct ShadowMap {
+    pass: RenderPass,
+    depth: TextureId,
+    size: u32,
+    pub(super) instanced_pipeline: Pipeline,
+    pub(super) mesh_pipeline: Pipeline,
+    /// Transforms world coordinates into the shadow map's clip space.
+    light_matrix: Mat4,
+}
+
+impl ShadowMap {
+    pub fn new(ctx: &mut GlContext, size: u32) -> Self {
+        let (pass, depth) = create_targets(ctx, size);
+        let shader = ctx
+            .new_shader(
+                ShaderSource::Glsl {
+                    vertex: VERTEX_SHADER,
+                    fragment: FRAGMENT_SHADER,
+                },
+                meta(),
+            )
+            .unwrap();
+        // Same layout as the instanced pipeline in `Shader`, so the same buffers can be used
+        let instanced_pipeline = ctx.new_pipeline(
+            &[
+                BufferLayout::default(),
+                BufferLayout {
+                    step_func: VertexStep::PerInstance,
+                    ..BufferLayout::default()
+                },
+            ],
+            &[
+                VertexAttribute::with_buffer("in_position", VertexFormat::Float3, 0),
+                VertexAttribute::with_buffer("in_normal", VertexFormat::Float3, 0),
+                VertexAttribute::with_buffer("in_inst_position", VertexFormat::Float3, 1),
+                VertexAttribute::with_buffer("in_inst_color", VertexFormat::Float4, 1),
+                VertexAttribute::with_buffer("is_water", VertexFormat::Int1, 1),
+            ],
+            shader,
+            params(),
+        );
+
+        let mesh_shader = ctx
+            .new_shader(
+                ShaderSource::Glsl {
+                    vertex: MESH_VERTEX_SHADER,
+                    fragment: FRAGMENT_SHADER,
+                },
+                meta(),
+            )
+            .unwrap();
+        let mesh_pipeline = ctx.new_pipeline(
+            &[BufferLayout::default()],
+            &[
+                VertexAttribute::new("in_position", VertexFormat::Float3),
+                VertexAttribute::new("in_normal", VertexFormat::Float3),
+                VertexAttribute::new("in_color", VertexFormat::Float4),
+                VertexAttribute::new("in_voxel", VertexFormat::Float3),
+                VertexAttribute::new("in_ao", VertexFormat::Float1),
+                VertexAttribute::new("is_water", VertexFormat::Int1),
+            ],
+            mesh_shader,
+            params(),
+        );
+
+        ShadowMap {
+            pass,
+            depth,
+            size,
+            instanced_pipeline,
+            mesh_pipeline,
+            light_matrix: Mat4::IDENTITY,
+        }
+    }
+
+    /// Recreates the shadow map if the size has changed.
+    pub fn resize(&mut self, ctx: &mut GlContext, size: u32) {
+        if size == self.size {
+            return;
+        }
+        // Deletes the textures too
+        ctx.delete_render_pass(self.pass);
+        (self.pass, self.depth) = create_targets(ctx, size);
+        self.size = size;
+    }
+
+    pub fn depth_texture(&self) -> TextureId {
+        self.depth
+    }
+
+    pub fn light_matrix(&self) -> Mat4 {
+        self.light_matrix
+    }
+
+    /// Centers the shadow map on `center`, covering `radius` in every direction, as seen from
+    /// the sun in `sun_direction`.
+    pub fn follow(&mut self, center: Vec3, sun_direction: Vec3, radius: f32) {
+        let direction = sun_direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        let view = Mat4::look_at_rh(Vec3::ZERO, -direction, up);
+        // Move the shadow map a whole texel at a time, otherwise the edges of shadows flicker
+        // when the camera moves
+        let texel = 2.0 * radius / self.size as f32;
+        let center = view.transform_point3(center);
+        let (x, y) = (
+            (center.x / texel).floor() * texel,
+            (center.y / texel).floor() * texel,
+        );
+        // The view looks along negative z, so distances are negated
+        let projection = Mat4::orthographic_rh_gl(
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            -center.z - radius - CASTER_DEPTH,
+            -center.z + radius + CASTER_DEPTH,
+        );
+        self.light_matrix = projection * view;
+    }
+
+    /// Starts drawing into the shadow map. End with `end_render_pass`.
+    pub fn begin_pass(&self, ctx: &mut GlContext) {
+        ctx.begin_pass(
+            Some(self.pass),
+            PassAction::Clear {
+                color: None,
+                depth: Some(1.0),
+                stencil: None,
+            },
+        );
+    }
+}
+
+fn create_targets(ctx: &mut GlContext, size: u32) -> (RenderPass, TextureId) {
+    let params = |format| TextureParams {
+        format,
+        width: size,
+        height: size,
+        wrap: TextureWrap::Clamp,
+        min_filter: FilterMode::Nearest,
+        mag_filter: FilterMode::Nearest,
+        ..Default::default()
+    };
+    let depth = ctx.new_render_texture(params(TextureFormat::Depth32));
+    // The colour is never looked at, but some drivers don't support render passes without it
+    let color = ctx.new_render_texture(params(TextureFormat::RGBA8));
+    let pass = ctx.new_render_pass(color, Some(depth));
+    (pass, depth)
+}
+
+fn params() -> PipelineParams {
+    PipelineParams {
+        depth_test: Comparison::Less,
+        depth_write: true,
+        // Both sides, since chunks aren't closed everywhere from the sun's point of view
+        cull_face: CullFace::Nothing,
+        ..Default::default()
+    }
+}
+
+fn meta() -> ShaderMeta {
+    ShaderMeta {
+        images: vec![],
+        uniforms: UniformBlockLayout {
+            uniforms: vec![UniformDesc::new("light_matrix", UniformType::Mat4)],
+        },
+    }
+}
+
+#[repr(C)]
+pub struct ShadowUniforms {
+    pub light_matrix: Mat4,
+}
@@ -0,0 +1,71 @@
+#version 330
+
+in vec2 screen_position;
+
+uniform mat4 inverse_view_projection;
+uniform mat4 star_matrix;
+uniform vec3 zenith_color;
+uniform vec3 horizon_color;
+uniform vec3 sun_direction;
+uniform vec3 moon_direction;
+uniform float star_visibility;
+
+const float SUN_RADIUS = 0.03;
+const float MOON_RADIUS = 0.04;
+// How many cells the stars are placed in along each axis of the unit cube around the camera
+const float STAR_CELLS = 120.0;
+// Fraction of cells that have a star
+const float STAR_DENSITY = 0.015;
+
+float hash(vec3 p) {
+    p = fract(p * vec3(0.1031, 0.1030, 0.0973));
+    p += dot(p, p.yxz + 33.33);
+    return fract((p.x + p.y) * p.z);
+}
+
+// A star field that is the same every night. Every cell of a grid around the camera has at
+// most one star, at a random place in the cell
+float stars(vec3 direction) {
+    vec3 p = direction * STAR_CELLS;
+    vec3 cell = floor(p);
+    if (hash(cell) > STAR_DENSITY) {
+        return 0.0;
+    }
+    vec3 star = cell + 0.25 + 0.5 * vec3(hash(cell + 1.0), hash(cell + 2.0), hash(cell + 3.0));
+    // Moved onto the sphere the direction is on, otherwise it could be too far away to be seen
+    star = normalize(star) * STAR_CELLS;
+    float brightness = 0.3 + 0.7 * hash(cell + 4.0);
+    return brightness * smoothstep(0.15, 0.0, distance(p, star));
+}
+
+void main(void) {
+    vec4 far = inverse_view_projection * vec4(screen_position, 1.0, 1.0);
+    vec3 direction = normalize(far.xyz / far.w);
+    float height = direction.y;
+
+    vec3 color = mix(horizon_color, zenith_color, sqrt(max(height, 0.0)));
+    // Below the horizon the sky fades to a darker horizon colour
+    color = mix(color, horizon_color * 0.6, smoothstep(0.0, -0.3, height));
+
+    float above_horizon = smoothstep(-0.02, 0.02, height);
+    vec3 star_direction = normalize((star_matrix * vec4(direction, 0.0)).xyz);
+    color += vec3(stars(star_direction)) * star_visibility * above_horizon;
+
+    // The sun is orange when it is low
+    vec3 sun_color = mix(vec3(1.0, 0.45, 0.15), vec3(1.0, 0.95, 0.8),
+                         smoothstep(-0.05, 0.4, sun_direction.y));
+    float sun = dot(direction, sun_direction);
+    float sun_distance = acos(clamp(sun, -1.0, 1.0));
+    float glow = pow(max(sun, 0.0), 8.0) * 0.25 + pow(max(sun, 0.0), 200.0) * 0.6;
+    color += sun_color * glow * above_horizon;
+    float sun_disc = smoothstep(SUN_RADIUS, SUN_RADIUS * 0.8, sun_distance);
+    color = mix(color, sun_color * 1.5, sun_disc * above_horizon);
+
+    float moon = dot(direction, moon_direction);
+    float moon_distance = acos(clamp(moon, -1.0, 1.0));
+    color += vec3(0.6, 0.65, 0.8) * pow(max(moon, 0.0), 300.0) * 0.3 * above_horizon;
+    float moon_disc = smoothstep(MOON_RADIUS, MOON_RADIUS * 0.9, moon_distance);
+    color = mix(color, vec3(0.85, 0.85, 0.9), moon_disc * above_horizon);
+
+    gl_FragColor = vec4(color, 1.0);
+}
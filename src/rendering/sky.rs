@@ -0,0 +1,136 @@
+//! The sky, drawn before everything else as a triangle covering the whole screen.
+//!
+//! The fragment shader turns each pixel back into the direction it looks in, and colours it
+//! from that: a gradient from the horizon to the zenith, the sun and the moon, and stars at
+//! night. Since the sky is infinitely far away, only the camera's rotation matters.
+
+use glam::{Mat3, Mat4, Vec2, Vec3};
+use miniquad::{
+    Bindings, BufferLayout, BufferSource, BufferType, BufferUsage, Comparison, GlContext, Pipeline,
+    PipelineParams, RenderingBackend as _, ShaderMeta, ShaderSource, UniformBlockLayout,
+    UniformDesc, UniformType, UniformsSource, VertexAttribute, VertexFormat,
+};
+
+const VERTEX_SHADER: &str = include_str!("sky.vert");
+const FRAGMENT_SHADER: &str = include_str!("sky.frag");
+
+/// Colours and directions of things in the sky.
+pub struct SkyParams {
+    pub zenith_color: Vec3,
+    pub horizon_color: Vec3,
+    pub sun_direction: Vec3,
+    pub moon_direction: Vec3,
+    /// Rotation of the stars, turning with the sun and moon.
+    pub star_rotation: Mat4,
+    /// From 0 during the day to 1 at night.
+    pub star_visibility: f32,
+}
+
+pub struct Sky {
+    pipeline: Pipeline,
+    bindings: Bindings,
+}
+
+impl Sky {
+    pub fn new(ctx: &mut GlContext) -> Self {
+        let shader = ctx
+            .new_shader(
+                ShaderSource::Glsl {
+                    vertex: VERTEX_SHADER,
+                    fragment: FRAGMENT_SHADER,
+                },
+                meta(),
+            )
+            .unwrap();
+
+        // One triangle large enough to cover the screen, which is -1 to 1 on both axes
+        let vertices = [
+            Vec2::new(-1.0, -1.0),
+            Vec2::new(3.0, -1.0),
+            Vec2::new(-1.0, 3.0),
+        ];
+        let vertex_buffer = ctx.new_buffer(
+            BufferType::VertexBuffer,
+            BufferUsage::Immutable,
+            BufferSource::slice(&vertices),
+        );
+        let index_buffer = ctx.new_buffer(
+            BufferType::IndexBuffer,
+            BufferUsage::Immutable,
+            BufferSource::slice(&[0u16, 1, 2]),
+        );
+
+        let pipeline = ctx.new_pipeline(
+            &[BufferLayout::default()],
+            &[VertexAttribute::new("in_position", VertexFormat::Float2)],
+            shader,
+            PipelineParams {
+                // Everything else is drawn on top
+                depth_test: Comparison::Always,
+                depth_write: false,
+                ..Default::default()
+            },
+        );
+
+        Sky {
+            pipeline,
+            bindings: Bindings {
+                vertex_buffers: vec![vertex_buffer],
+                index_buffer,
+                images: vec![],
+            },
+        }
+    }
+
+    pub fn draw(
+        &self,
+        ctx: &mut GlContext,
+        proj_matrix: Mat4,
+        camera_matrix: Mat4,
+        params: &SkyParams,
+    ) {
+        // Only the rotation of the camera, so the sky stays infinitely far away
+        let rotation = Mat4::from_mat3(Mat3::from_mat4(camera_matrix));
+        ctx.apply_pipeline(&self.pipeline);
+        ctx.apply_bindings(&self.bindings);
+        ctx.apply_uniforms(UniformsSource::table(&SkyUniforms {
+            inverse_view_projection: (proj_matrix * rotation).inverse(),
+            star_matrix: params.star_rotation,
+            zenith_color: params.zenith_color,
+            horizon_color: params.horizon_color,
+            sun_direction: params.sun_direction,
+            moon_direction: params.moon_direction,
+            star_visibility: params.star_visibility,
+        }));
+        ctx.draw(0, 3, 1);
+    }
+}
+
+fn meta() -> ShaderMeta {
+    ShaderMeta {
+        images: vec![],
+        uniforms: UniformBlockLayout {
+            // The order here needs to match the order in the SkyUniforms struct
+            uniforms: vec![
+                UniformDesc::new("inverse_view_projection", UniformType::Mat4),
+                UniformDesc::new("star_matrix", UniformType::Mat4),
+                UniformDesc::new("zenith_color", UniformType::Float3),
+                UniformDesc::new("horizon_color", UniformType::Float3),
+                UniformDesc::new("sun_direction", UniformType::Float3),
+                UniformDesc::new("moon_direction", UniformType::Float3),
+                UniformDesc::new("star_visibility", UniformType::Float1),
+            ],
+        },
+    }
+}
+
+#[repr(C)]
+struct SkyUniforms {
+    inverse_view_projection: Mat4,
+    star_matrix: Mat4,
+    zenith_color: Vec3,
+    horizon_color: Vec3,
+    sun_direction: Vec3,
+    moon_direction: Vec3,
+    star_visibility: f32,
+}
@@ -0,0 +1,10 @@
+#version 330
+
+in vec2 in_position;
+
+out vec2 screen_position;
+
+void main(void) {
+    screen_position = in_position;
+    gl_Position = vec4(in_position, 0.0, 1.0);
+}
@@ -0,0 +1,371 @@
+//! Settings that can be tweaked in the Debug window, saved to a file as named presets.
+//!
+//! The file contains one section per preset, each starting with `[name]` followed by one
+//! `name = value` per line. Settings missing from a preset keep their default value.
+
+#[cfg(feature = "egui")]
+use std::fmt::Write as _;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use glam::Vec4;
+
+use crate::rendering::{RenderPath, ShaderSettings};
+
+pub const DEFAULT_PRESET: &str = "default";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    /// How many chunks to render in each direction from the camera.
+    pub render_distance: i32,
+    /// How many chunks in each direction are drawn at full detail. Chunks further away are
+    /// drawn coarser and coarser, the level halving every time the distance doubles.
+    pub lod_distance: i32,
+    /// How many chunks in each direction large objects like trees are drawn at full detail in.
+    /// Further away they are drawn as coarse proxies.
+    pub object_detail_distance: i32,
+    /// How many large objects can be drawn at full detail, nearest first. The rest are proxies.
+    pub object_budget: usize,
+    pub render_path: RenderPath,
+    pub flying_movement_speed: f32,
+    pub on_ground_movement_speed: f32,
+    /// Length of a day in seconds.
+    pub day_length: f32,
+    /// How many times faster than normal the world clock runs.
+    pub time_speed: f32,
+    pub shader: ShaderSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            render_distance: 8,
+            lod_distance: 4,
+            object_detail_distance: 4,
+            object_budget: 2000,
+            render_path: RenderPath::Greedy,
+            flying_movement_speed: 10.0,
+            on_ground_movement_speed: 40.0,
+            day_length: 600.0,
+            time_speed: 1.0,
+            shader: ShaderSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let number = || -> Result<f32, String> {
+            value
+                .parse()
+                .ok()
+                .filter(|v: &f32| v.is_finite())
+                .ok_or(format!(
+                    "invalid value {value:?} for {name}, expected a number"
+                ))
+        };
+        // The same ranges as the sliders
+        let number_in = |range: RangeInclusive<f32>| -> Result<f32, String> {
+            Some(number()?).filter(|v| range.contains(v)).ok_or(format!(
+                "invalid value {value:?} for {name}, expected {} to {}",
+                range.start(),
+                range.end()
+            ))
+        };
+        let color = || -> Result<Vec4, String> {
+            let rgb: Vec<f32> = value
+                .split(',')
+                .map(|v| v.trim().parse())
+                .collect::<Result<_, _>>()
+                .unwrap_or_default();
+            match rgb[..] {
+                [r, g, b] => Ok(Vec4::new(r, g, b, 1.0)),
+                _ => Err(format!(
+                    "invalid value {value:?} for {name}, expected a colour like 1.0,0.5,0.2"
+                )),
+            }
+        };
+
+        let shader = &mut self.shader;
+        match name {
+            "render-distance" => {
+                let distance = value.parse().ok().filter(|d| (1..=64).contains(d));
+                self.render_distance = distance.ok_or(format!(
+                    "invalid value {value:?} for {name}, expected 1 to 64"
+                ))?;
+            }
+            "lod-distance" => {
+                let distance = value.parse().ok().filter(|d| (1..=64).contains(d));
+                self.lod_distance = distance.ok_or(format!(
+                    "invalid value {value:?} for {name}, expected 1 to 64"
+                ))?;
+            }
+            "object-detail-distance" => {
+                let distance = value.parse().ok().filter(|d| (0..=64).contains(d));
+                self.object_detail_distance = distance.ok_or(format!(
+                    "invalid value {value:?} for {name}, expected 0 to 64"
+                ))?;
+            }
+            "object-budget" => {
+                self.object_budget = value.parse().map_err(|_| {
+                    format!("invalid value {value:?} for {name}, expected a whole number")
+                })?;
+            }
+            "render-path" => self.render_path = value.parse()?,
+            "flying-movement-speed" => self.flying_movement_speed = number()?,
+            "on-ground-movement-speed" => self.on_ground_movement_speed = number()?,
+            "day-length" => self.day_length = number_in(10.0..=3600.0)?,
+            "time-speed" => self.time_speed = number_in(0.1..=100.0)?,
+            "sun-color" => shader.sun_color = color()?,
+            "ambient-light-color" => shader.ambient_light_color = color()?,
+            "ambient-water-activity" => shader.ambient_water_activity = number()?,
+            "wave-water-peak" => shader.wave_water_peak = number()?,
+            "wave-water-pow" => shader.wave_water_pow = number()?,
+            "wave-water-x-factor" => shader.wave_water_x_factor = number()?,
+            "wave-water-z-factor" => shader.wave_water_z_factor = number()?,
+            "wave-water-frequency" => shader.wave_water_frequency = number()?,
+            "ambient-occlusion" => {
+                shader.ambient_occlusion = value.parse().map_err(|_| {
+                    format!("invalid value {value:?} for {name}, expected true or false")
+                })?;
+            }
+            "ambient-occlusion-strength" => {
+                shader.ambient_occlusion_strength = number_in(0.0..=1.0)?;
+            }
+            "shadows" => {
+                shader.shadows = value.parse().map_err(|_| {
+                    format!("invalid value {value:?} for {name}, expected true or false")
+                })?;
+            }
+            "shadow-map-size" => {
+                let size = value
+                    .parse()
+                    .ok()
+                    .filter(|s: &u32| s.is_power_of_two() && (512..=8192).contains(s));
+                shader.shadow_map_size = size.ok_or(format!(
+                    "invalid value {value:?} for {name}, expected a power of two from 512 to 8192"
+                ))?;
+            }
+            "shadow-bias" => shader.shadow_bias = number_in(0.00001..=0.01)?,
+            "shadow-distance" => shader.shadow_distance = number_in(16.0..=512.0)?,
+            "fog" => shader.fog = value.parse()?,
+            "fog-start" => shader.fog_start = number_in(0.0..=1.0)?,
+            _ => return Err(format!("unknown setting {name}")),
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "egui")]
+    fn write(&self, out: &mut String) {
+        let color = |c: Vec4| format!("{},{},{}", c.x, c.y, c.z);
+        let shader = &self.shader;
+        for (name, value) in [
+            ("render-distance", self.render_distance.to_string()),
+            ("lod-distance", self.lod_distance.to_string()),
+            (
+                "object-detail-distance",
+                self.object_detail_distance.to_string(),
+            ),
+            ("object-budget", self.object_budget.to_string()),
+            ("render-path", self.render_path.to_string()),
+            (
+                "flying-movement-speed",
+                self.flying_movement_speed.to_string(),
+            ),
+            (
+                "on-ground-movement-speed",
+                self.on_ground_movement_speed.to_string(),
+            ),
+            ("day-length", self.day_length.to_string()),
+            ("time-speed", self.time_speed.to_string()),
+            ("sun-color", color(shader.sun_color)),
+            ("ambient-light-color", color(shader.ambient_light_color)),
+            (
+                "ambient-water-activity",
+                shader.ambient_water_activity.to_string(),
+            ),
+            ("wave-water-peak", shader.wave_water_peak.to_string()),
+            ("wave-water-pow", shader.wave_water_pow.to_string()),
+            (
+                "wave-water-x-factor",
+                shader.wave_water_x_factor.to_string(),
+            ),
+            (
+                "wave-water-z-factor",
+                shader.wave_water_z_factor.to_string(),
+            ),
+            (
+                "wave-water-frequency",
+                shader.wave_water_frequency.to_string(),
+            ),
+            ("ambient-occlusion", shader.ambient_occlusion.to_string()),
+            (
+                "ambient-occlusion-strength",
+                shader.ambient_occlusion_strength.to_string(),
+            ),
+            ("shadows", shader.shadows.to_string()),
+            ("shadow-map-size", shader.shadow_map_size.to_string()),
+            ("shadow-bias", shader.shadow_bias.to_string()),
+            ("shadow-distance", shader.shadow_distance.to_string()),
+            ("fog", shader.fog.to_string()),
+            ("fog-start", shader.fog_start.to_string()),
+        ] {
+            writeln!(out, "{name} = {value}").unwrap();
+        }
+    }
+}
+
+/// All presets in a settings file, in the order they appear.
+#[derive(Default)]
+pub struct SettingsFile {
+    presets: Vec<(String, Settings)>,
+}
+
+impl SettingsFile {
+    /// Reads a settings file. A missing file is treated as an empty one.
+    pub fn load(path: &Path) -> Result<SettingsFile, String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(SettingsFile::default()),
+            Err(e) => return Err(format!("couldn't read {}: {e}", path.display())),
+        };
+        SettingsFile::parse(&contents, path)
+    }
+
+    /// Parses the contents of the settings file at `path`, which is only used in errors.
+    fn parse(contents: &str, path: &Path) -> Result<SettingsFile, String> {
+        let mut file = SettingsFile::default();
+        for (i, line) in contents.lines().enumerate() {
+            let error = |e: String| format!("{}:{}: {e}", path.display(), i + 1);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if file.get(name).is_some() {
+                    return Err(error(format!("preset {name:?} appears more than once")));
+                }
+                file.insert(name, Settings::default());
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `[preset]` or `name = value`".to_string()))?;
+            let (_, settings) = file
+                .presets
+                .last_mut()
+                .ok_or_else(|| error("expected `[preset]` before the first setting".to_string()))?;
+            settings.set(name.trim(), value.trim()).map_err(error)?;
+        }
+        Ok(file)
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = String::from("# Voxel Garden settings\n");
+        for (name, settings) in &self.presets {
+            writeln!(out, "\n[{name}]").unwrap();
+            settings.write(&mut out);
+        }
+        std::fs::write(path, out).map_err(|e| format!("couldn't write {}: {e}", path.display()))
+    }
+
+    pub fn get(&self, preset: &str) -> Option<Settings> {
+        self.presets
+            .iter()
+            .find(|(name, _)| name == preset)
+            .map(|(_, settings)| *settings)
+    }
+
+    /// Adds a preset, replacing any preset with the same name.
+    pub fn insert(&mut self, preset: &str, settings: Settings) {
+        match self.presets.iter_mut().find(|(name, _)| name == preset) {
+            Some((_, existing)) => *existing = settings,
+            None => self.presets.push((preset.to_string(), settings)),
+        }
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn preset_names(&self) -> impl Iterator<Item = &str> {
+        self.presets.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rendering::FogMode;
+
+    use super::*;
+
+    fn parse(contents: &str) -> Result<SettingsFile, String> {
+        SettingsFile::parse(contents, Path::new("test.cfg"))
+    }
+
+    #[test]
+    fn settings_go_into_their_preset() {
+        let file = parse(
+            "# comment\n[a]\nrender-distance = 3\n\n[b]\nrender-distance = 5\nshadows = false\n\
+             fog = exponential\nfog-start = 0.25\nrender-path = instanced\n",
+        )
+        .unwrap();
+        let a = file.get("a").unwrap();
+        let b = file.get("b").unwrap();
+        assert_eq!(a.render_distance, 3);
+        assert!(a.shader.shadows);
+        assert_eq!(b.render_distance, 5);
+        assert!(!b.shader.shadows);
+        assert_eq!(b.shader.fog, FogMode::Exponential);
+        assert_eq!(b.shader.fog_start, 0.25);
+        assert_eq!(b.render_path, RenderPath::Instanced);
+        // Missing settings keep their default
+        assert_eq!(a.lod_distance, Settings::default().lod_distance);
+        assert!(file.get("c").is_none());
+    }
+
+    #[test]
+    fn duplicate_presets_are_rejected() {
+        let error = parse("[a]\nrender-distance = 3\n[b]\n[a]\nrender-distance = 4\n")
+            .err()
+            .unwrap();
+        assert!(error.starts_with("test.cfg:4:"), "{error}");
+    }
+
+    #[test]
+    fn invalid_lines_are_errors() {
+        assert!(parse("render-distance = 3\n").is_err());
+        assert!(parse("[a]\nrender-distance\n").is_err());
+        assert!(parse("[a]\nrender-distance = 100\n").is_err());
+        assert!(parse("[a]\nno-such-setting = 1\n").is_err());
+        assert!(parse("[a]\nsun-color = 1,2\n").is_err());
+        assert!(parse("[a]\nrender-path = fast\n").is_err());
+        assert!(parse("[a]\nfog = thick\n").is_err());
+        assert!(parse("[a]\nfog-start = 1.5\n").is_err());
+        assert!(parse("[a]\nfog-start = -0.1\n").is_err());
+        assert!(parse("[a]\nday-length = 0\n").is_err());
+        assert!(parse("[a]\ntime-speed = 0\n").is_err());
+        assert!(parse("[a]\ntime-speed = -1\n").is_err());
+        assert!(parse("[a]\ntime-speed = 1000\n").is_err());
+        assert!(parse("[a]\nambient-occlusion-strength = 2\n").is_err());
+        assert!(parse("[a]\nshadow-bias = 0\n").is_err());
+        assert!(parse("[a]\nshadow-bias = 0.1\n").is_err());
+        assert!(parse("[a]\nshadow-distance = 8\n").is_err());
+        assert!(parse("[a]\nshadow-distance = 1000\n").is_err());
+    }
+
+    #[cfg(feature = "egui")]
+    #[test]
+    fn written_settings_read_back() {
+        let mut settings = Settings {
+            render_distance: 12,
+            object_budget: 42,
+            ..Settings::default()
+        };
+        settings.shader.sun_color = Vec4::new(0.5, 0.25, 1.0, 1.0);
+        settings.shader.shadow_map_size = 4096;
+        let mut contents = String::from("[x]\n");
+        settings.write(&mut contents);
+        assert_eq!(parse(&contents).unwrap().get("x"), Some(settings));
+    }
+}
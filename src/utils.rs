@@ -1,7 +1,4 @@
 #![allow(unused)]
-
-use std::f32::EPSILON;
-
 use glam::{Mat4, Vec3, Vec4};
 
 use crate::Point;
@@ -26,7 +23,11 @@ pub const WATER_BLUE: Vec4 = Vec4::new(
 /// Port of arbRotate from lab material
 pub fn arb_rotate(axis: Vec3, angle: f32) -> Mat4 {
     // Check if parrallel to Z
-    if axis.x < EPSILON && axis.x > -EPSILON && axis.y < EPSILON && axis.y > -EPSILON {
+    if axis.x < f32::EPSILON
+        && axis.x > -f32::EPSILON
+        && axis.y < f32::EPSILON
+        && axis.y > -f32::EPSILON
+    {
         if axis.z > 0.0 {
             return glam::Mat4::from_rotation_z(angle);
         } else {
@@ -0,0 +1,415 @@
+//! Dense voxel storage for a single chunk.
+
+use std::collections::HashMap;
+
+use glam::{IVec2, IVec3, Vec3, Vec4};
+
+use crate::InstanceData;
+
+/// What a single voxel is made of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub color: Vec4,
+    pub is_water: bool,
+}
+
+impl Material {
+    pub fn new(color: Vec4) -> Self {
+        Material {
+            color,
+            is_water: false,
+        }
+    }
+
+    pub fn water(color: Vec4) -> Self {
+        Material {
+            color,
+            is_water: true,
+        }
+    }
+
+    fn key(&self) -> [u32; 5] {
+        let [r, g, b, a] = self.color.to_array().map(f32::to_bits);
+        [r, g, b, a, self.is_water as u32]
+    }
+}
+
+/// Index into the palette of a chunk. 0 is always air.
+pub type MaterialId = u16;
+
+pub const AIR: MaterialId = 0;
+
+/// A `width` x `height` x `depth` grid of voxels.
+///
+/// Coordinates are local to the chunk in x and z, so they go from 0 to `width`/`depth`, but y is
+/// the same as in the world. The grid only covers the y-range that contains voxels and grows when
+/// voxels are set above or below it.
+///
+/// Every voxel is stored as a [`MaterialId`] that indexes into a palette of the materials used
+/// in the chunk.
+#[derive(Clone)]
+pub struct ChunkVoxels {
+    width: i32,
+    depth: i32,
+    min_y: i32,
+    height: i32,
+    ids: Vec<MaterialId>,
+    palette: Vec<Material>,
+    palette_lookup: HashMap<[u32; 5], MaterialId>,
+}
+
+impl ChunkVoxels {
+    pub fn new(width: i32, depth: i32) -> Self {
+        ChunkVoxels {
+            width,
+            depth,
+            min_y: 0,
+            height: 0,
+            ids: Vec::new(),
+            // Index 0 is air and never looked at, so any material works as a placeholder
+            palette: vec![Material::new(Vec4::ZERO)],
+            palette_lookup: HashMap::new(),
+        }
+    }
+
+    /// Creates a grid containing `voxels`. Later voxels replace earlier ones at the same
+    /// position. Voxels outside of the chunk in x or z are ignored.
+    pub fn from_voxels(width: i32, depth: i32, voxels: &[(IVec3, Material)]) -> Self {
+        let mut chunk = ChunkVoxels::new(width, depth);
+        if let (Some(min_y), Some(max_y)) = (
+            voxels.iter().map(|(p, _)| p.y).min(),
+            voxels.iter().map(|(p, _)| p.y).max(),
+        ) {
+            chunk.grow_to(min_y, max_y);
+        }
+        for &(position, material) in voxels {
+            chunk.set(position, Some(material));
+        }
+        chunk
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> i32 {
+        self.depth
+    }
+
+    /// The y-range covered by the grid, as (inclusive min, exclusive max).
+    pub fn y_range(&self) -> (i32, i32) {
+        (self.min_y, self.min_y + self.height)
+    }
+
+    pub fn contains(&self, p: IVec3) -> bool {
+        p.x >= 0 && p.x < self.width && p.z >= 0 && p.z < self.depth
+    }
+
+    fn index(&self, p: IVec3) -> Option<usize> {
+        let y = p.y - self.min_y;
+        (self.contains(p) && y >= 0 && y < self.height)
+            .then(|| ((y * self.depth + p.z) * self.width + p.x) as usize)
+    }
+
+    pub fn get_id(&self, p: IVec3) -> MaterialId {
+        self.index(p).map_or(AIR, |i| self.ids[i])
+    }
+
+    pub fn get(&self, p: IVec3) -> Option<Material> {
+        match self.get_id(p) {
+            AIR => None,
+            id => Some(self.palette[id as usize]),
+        }
+    }
+
+    pub fn material(&self, id: MaterialId) -> Option<Material> {
+        (id != AIR).then(|| self.palette[id as usize])
+    }
+
+    /// Sets the voxel at `p`, or removes it if `material` is `None`. Returns false if `p` is
+    /// outside of the chunk in x or z.
+    pub fn set(&mut self, p: IVec3, material: Option<Material>) -> bool {
+        if !self.contains(p) {
+            return false;
+        }
+        let id = match material {
+            Some(material) => self.material_id(material),
+            None => AIR,
+        };
+        if self.index(p).is_none() {
+            if id == AIR {
+                return true;
+            }
+            self.grow_to(p.y, p.y);
+        }
+        let i = self.index(p).unwrap();
+        self.ids[i] = id;
+        true
+    }
+
+    fn material_id(&mut self, material: Material) -> MaterialId {
+        if let Some(&id) = self.palette_lookup.get(&material.key()) {
+            return id;
+        }
+        // Materials are never removed from the palette when their last voxel is replaced, so
+        // it can fill up with materials that aren't used anymore
+        if self.palette.len() > MaterialId::MAX as usize {
+            self.compact_palette();
+        }
+        match MaterialId::try_from(self.palette.len()) {
+            Ok(id) => {
+                self.palette.push(material);
+                self.palette_lookup.insert(material.key(), id);
+                id
+            }
+            // Every material is in use, so the closest one will have to do
+            Err(_) => self.closest_material(material),
+        }
+    }
+
+    /// Removes materials that no voxel uses from the palette.
+    fn compact_palette(&mut self) {
+        let mut new_ids = vec![AIR; self.palette.len()];
+        let mut palette = vec![self.palette[AIR as usize]];
+        for id in &mut self.ids {
+            if *id == AIR {
+                continue;
+            }
+            if new_ids[*id as usize] == AIR {
+                new_ids[*id as usize] = palette.len() as MaterialId;
+                palette.push(self.palette[*id as usize]);
+            }
+            *id = new_ids[*id as usize];
+        }
+        self.palette_lookup = palette
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(id, material)| (material.key(), id as MaterialId))
+            .collect();
+        self.palette = palette;
+    }
+
+    /// The material in the palette most like `material`, preferring ones that are water if
+    /// `material` is and the other way around.
+    fn closest_material(&self, material: Material) -> MaterialId {
+        let difference = |m: &Material| {
+            let water = if m.is_water == material.is_water {
+                0.0
+            } else {
+                f32::INFINITY
+            };
+            water + m.color.distance_squared(material.color)
+        };
+        (1..self.palette.len())
+            .min_by(|&a, &b| difference(&self.palette[a]).total_cmp(&difference(&self.palette[b])))
+            .unwrap_or(AIR as usize) as MaterialId
+    }
+
+    /// Makes sure the grid covers y from `min_y` to `max_y` (inclusive).
+    fn grow_to(&mut self, min_y: i32, max_y: i32) {
+        let (old_min, old_max) = self.y_range();
+        let (new_min, new_max) = if self.height == 0 {
+            (min_y, max_y + 1)
+        } else {
+            (old_min.min(min_y), old_max.max(max_y + 1))
+        };
+        if (new_min, new_max) == (old_min, old_max) {
+            return;
+        }
+
+        let layer = (self.width * self.depth) as usize;
+        let mut ids = vec![AIR; layer * (new_max - new_min) as usize];
+        if self.height > 0 {
+            let offset = layer * (old_min - new_min) as usize;
+            ids[offset..offset + self.ids.len()].copy_from_slice(&self.ids);
+        }
+        self.ids = ids;
+        self.min_y = new_min;
+        self.height = new_max - new_min;
+    }
+
+    /// All non-air voxels with their local position.
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, Material)> + '_ {
+        let (width, depth) = (self.width, self.depth);
+        self.ids
+            .iter()
+            .enumerate()
+            .filter(|(_, &id)| id != AIR)
+            .map(move |(i, &id)| {
+                let i = i as i32;
+                let p = IVec3::new(
+                    i % width,
+                    i / (width * depth) + self.min_y,
+                    (i / width) % depth,
+                );
+                (p, self.palette[id as usize])
+            })
+    }
+
+    /// A coarser copy of the grid where every `factor` x `factor` x `factor` block of voxels is
+    /// one voxel, for drawing the chunk far away. Blocks are aligned to multiples of `factor` in
+    /// world y. A block is solid if any voxel in it is, so thin things like tree trunks don't
+    /// disappear, and it gets the material of its topmost opaque voxel so the ground keeps the
+    /// colour of its surface.
+    pub fn downsample(&self, factor: i32) -> ChunkVoxels {
+        let mut blocks: HashMap<IVec3, (IVec3, Material)> = HashMap::new();
+        for (p, material) in self.iter() {
+            let block = p.div_euclid(IVec3::splat(factor));
+            let replace = match blocks.get(&block) {
+                None => true,
+                // Opaque voxels win over water, and higher voxels over lower
+                Some((q, m)) => {
+                    (m.is_water && !material.is_water)
+                        || (m.is_water == material.is_water && p.y > q.y)
+                }
+            };
+            if replace {
+                blocks.insert(block, (p, material));
+            }
+        }
+        let voxels: Vec<(IVec3, Material)> = blocks
+            .into_iter()
+            .map(|(block, (_, material))| (block, material))
+            .collect();
+        let size = |n: i32| (n + factor - 1) / factor;
+        ChunkVoxels::from_voxels(size(self.width), size(self.depth), &voxels)
+    }
+
+    /// Whether the voxel at `p` hides the faces of its neighbours. Water moves with the waves,
+    /// so it never hides anything.
+    pub(crate) fn is_opaque(&self, p: IVec3) -> bool {
+        self.material(self.get_id(p)).is_some_and(|m| !m.is_water)
+    }
+
+    /// Instance data for every voxel that isn't completely surrounded by other voxels, in world
+    /// coordinates. `origin` is the world coordinate of the chunk's corner.
+    pub fn instances(&self, origin: IVec2) -> Vec<InstanceData> {
+        const NEIGHBOURS: [IVec3; 6] = [
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ];
+        let origin = IVec3::new(origin.x, 0, origin.y);
+        self.iter()
+            .filter(|&(p, _)| !NEIGHBOURS.iter().all(|&d| self.is_opaque(p + d)))
+            .map(|(p, material)| InstanceData {
+                position: (p + origin).as_vec3(),
+                color: material.color,
+                is_water: material.is_water as u32,
+            })
+            .collect()
+    }
+}
+
+/// Rounds a position in world space to the voxel containing it.
+pub fn voxel_at(position: Vec3) -> IVec3 {
+    position.round().as_ivec3()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(i: u32) -> Material {
+        Material::new(Vec4::new(i as f32 / 100_000.0, 0.5, 0.5, 1.0))
+    }
+
+    #[test]
+    fn replaced_materials_free_their_palette_entry() {
+        let mut voxels = ChunkVoxels::new(4, 4);
+        voxels.set(IVec3::new(1, 0, 1), Some(Material::water(Vec4::ONE)));
+        // Far more materials than fit in the palette, but only two in use at a time
+        for i in 0..70_000 {
+            voxels.set(IVec3::new(0, 0, 0), Some(color(i)));
+        }
+        assert_eq!(voxels.get(IVec3::new(0, 0, 0)), Some(color(69_999)));
+        assert_eq!(
+            voxels.get(IVec3::new(1, 0, 1)),
+            Some(Material::water(Vec4::ONE))
+        );
+        assert!(voxels.palette.len() < 70_000);
+    }
+
+    #[test]
+    fn a_full_palette_falls_back_to_the_closest_material() {
+        let mut voxels = ChunkVoxels::new(256, 256);
+        for i in 0..MaterialId::MAX as u32 {
+            let p = IVec3::new((i % 256) as i32, 0, (i / 256) as i32);
+            voxels.set(p, Some(color(i * 2)));
+        }
+        let p = IVec3::new(255, 0, 255);
+        voxels.set(p, Some(color(1001)));
+        let closest = voxels.get(p).unwrap();
+        assert!(closest == color(1000) || closest == color(1002));
+    }
+
+    #[test]
+    fn set_grows_the_grid() {
+        let mut voxels = ChunkVoxels::new(4, 4);
+        assert!(voxels.set(IVec3::new(0, 5, 0), Some(color(1))));
+        assert!(voxels.set(IVec3::new(3, -2, 3), Some(color(2))));
+        assert!(!voxels.set(IVec3::new(4, 0, 0), Some(color(3))));
+        assert_eq!(voxels.y_range(), (-2, 6));
+        assert_eq!(voxels.get(IVec3::new(0, 5, 0)), Some(color(1)));
+        assert_eq!(voxels.get(IVec3::new(3, -2, 3)), Some(color(2)));
+        assert_eq!(voxels.iter().count(), 2);
+    }
+
+    #[test]
+    fn downsampled_blocks_are_solid_if_any_voxel_is() {
+        let voxels = ChunkVoxels::from_voxels(8, 8, &[(IVec3::new(3, 5, 6), color(1))]);
+        let blocks = voxels.downsample(2);
+        assert_eq!(
+            blocks.iter().collect::<Vec<_>>(),
+            [(IVec3::new(1, 2, 3), color(1))]
+        );
+        let blocks = voxels.downsample(4);
+        assert_eq!(
+            blocks.iter().collect::<Vec<_>>(),
+            [(IVec3::new(0, 1, 1), color(1))]
+        );
+    }
+
+    #[test]
+    fn downsampled_blocks_take_the_topmost_opaque_material() {
+        let water = Material::water(Vec4::ONE);
+        let voxels = ChunkVoxels::from_voxels(
+            4,
+            4,
+            &[
+                (IVec3::new(0, 0, 0), color(1)),
+                (IVec3::new(1, 1, 1), color(2)),
+                // Water on top doesn't hide the ground under it
+                (IVec3::new(2, 0, 0), color(3)),
+                (IVec3::new(3, 1, 1), water),
+                (IVec3::new(0, 2, 0), water),
+            ],
+        );
+        let blocks = voxels.downsample(2);
+        assert_eq!(blocks.get(IVec3::new(0, 0, 0)), Some(color(2)));
+        assert_eq!(blocks.get(IVec3::new(1, 0, 0)), Some(color(3)));
+        assert_eq!(blocks.get(IVec3::new(0, 1, 0)), Some(water));
+    }
+
+    #[test]
+    fn downsampled_blocks_are_aligned_in_world_y() {
+        let voxels = ChunkVoxels::from_voxels(
+            5,
+            5,
+            &[
+                (IVec3::new(4, -1, 4), color(1)),
+                (IVec3::new(0, 2, 0), color(2)),
+            ],
+        );
+        let blocks = voxels.downsample(2);
+        // Partial blocks at the edges count as whole blocks
+        assert_eq!((blocks.width(), blocks.depth()), (3, 3));
+        assert_eq!(blocks.get(IVec3::new(2, -1, 2)), Some(color(1)));
+        assert_eq!(blocks.get(IVec3::new(0, 1, 0)), Some(color(2)));
+        assert_eq!(blocks.iter().count(), 2);
+    }
+}
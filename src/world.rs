@@ -0,0 +1,48 @@
+use glam::IVec2;
+
+use crate::biomes::BiomeConfig;
+use crate::terrain::{generate_terrain, GenerationPositions, TerrainConfig};
+use crate::CHUNK_SIZE;
+
+/// Everything needed to generate the world, one chunk at a time.
+///
+/// ```no_run
+/// # fn example(world: voxel_garden::World) {
+/// let chunk = world.generate_chunk(glam::IVec2::new(0, -1));
+/// println!("{} visible voxels", chunk.instances().len());
+/// # }
+/// ```
+pub struct World {
+    pub terrain_config: TerrainConfig,
+    pub biome_config: BiomeConfig,
+}
+
+impl World {
+    pub fn new(terrain_config: TerrainConfig, biome_config: BiomeConfig) -> Self {
+        World {
+            terrain_config,
+            biome_config,
+        }
+    }
+
+    /// Generates the ground and objects of a chunk. The chunk's corner is at
+    /// `chunk * CHUNK_SIZE` in world coordinates.
+    pub fn generate_chunk(&self, chunk: IVec2) -> GenerationPositions {
+        generate_terrain(
+            chunk.x * CHUNK_SIZE,
+            chunk.y * CHUNK_SIZE,
+            &self.terrain_config,
+            &self.biome_config,
+        )
+    }
+
+    /// Whether the chunk is inside the world bounds and should be generated.
+    pub fn chunk_in_bounds(&self, chunk: IVec2) -> bool {
+        self.terrain_config.chunk_in_bounds(chunk)
+    }
+
+    /// Height of the terrain surface at world coordinate (x, z).
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        self.terrain_config.sample(x, z)
+    }
+}
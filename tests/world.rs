@@ -0,0 +1,232 @@
+//! Generating the world without a window or GPU.
+
+use glam::{IVec2, IVec3, Quat, Vec3, Vec4};
+use noise::Perlin;
+
+use voxel_garden::biomes::BiomeConfig;
+use voxel_garden::chunks::ObjectId;
+use voxel_garden::terrain::{TerrainConfig, WorldBounds};
+use voxel_garden::{InstanceData, Model, World, CHUNK_SIZE};
+
+fn world(bounds: WorldBounds) -> World {
+    let terrain = TerrainConfig {
+        sample_rate: 0.004,
+        width: CHUNK_SIZE,
+        height: 20,
+        depth: CHUNK_SIZE,
+        max_height: 40.0,
+        min_height: 6.0,
+        snow_line: 0.75,
+        rock_line: 0.68,
+        max_slope: 1.5,
+        noise: Perlin::new(555),
+        heightmap: None,
+        bounds,
+    };
+    let biomes = BiomeConfig {
+        noise: Perlin::new(666),
+        biome_sample_rate: 0.001,
+        plant_sample_rate: 0.3,
+    };
+    World::new(terrain, biomes)
+}
+
+#[test]
+fn ground_is_generated_at_the_terrain_height() {
+    let world = world(WorldBounds::Infinite);
+    let chunk = IVec2::new(1, -2);
+    let generated = world.generate_chunk(chunk);
+    assert_eq!(generated.origin, chunk * CHUNK_SIZE);
+    assert!(!generated.instances().is_empty());
+
+    let mut land = 0;
+    for z in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            let world_x = generated.origin.x + x;
+            let world_z = generated.origin.y + z;
+            let height = world.height_at(world_x as f32, world_z as f32).trunc();
+            if height <= world.terrain_config.min_height {
+                continue;
+            }
+            land += 1;
+            let ground = generated.voxels.get(IVec3::new(x, height as i32, z));
+            assert!(
+                ground.is_some_and(|material| !material.is_water),
+                "no ground at ({world_x}, {height}, {world_z})"
+            );
+        }
+    }
+    assert!(land > 0, "the test chunk should have some land");
+}
+
+#[test]
+fn finite_worlds_stop_at_the_wall() {
+    // Not a multiple of CHUNK_SIZE, so the outermost chunks are only partly inside
+    let world = world(WorldBounds::Finite {
+        size: IVec2::new(40, 40),
+    });
+    // The world covers -20..20 on both axes
+    assert!(world.chunk_in_bounds(IVec2::new(0, 0)));
+    assert!(world.chunk_in_bounds(IVec2::new(-1, -1)));
+    assert!(!world.chunk_in_bounds(IVec2::new(1, 0)));
+    assert!(!world.chunk_in_bounds(IVec2::new(0, -2)));
+
+    let generated = world.generate_chunk(IVec2::new(0, 0));
+    for (p, _) in generated.voxels.iter() {
+        let world_position = p + IVec3::new(generated.origin.x, 0, generated.origin.y);
+        assert!(
+            world_position.x < 20 && world_position.z < 20,
+            "voxel outside the wall at {world_position}"
+        );
+    }
+}
+
+#[test]
+fn islands_only_generate_chunks_near_the_island() {
+    let world = world(WorldBounds::Island { radius: 100.0 });
+    assert!(world.chunk_in_bounds(IVec2::new(0, 0)));
+    assert!(world.chunk_in_bounds(IVec2::new(-3, 2)));
+    assert!(!world.chunk_in_bounds(IVec2::new(10, 0)));
+    // The terrain falls off to the sea floor at the edge of the island
+    assert_eq!(world.height_at(120.0, 0.0), 0.0);
+}
+
+#[test]
+fn removed_generated_objects_stay_removed() {
+    let world = world(WorldBounds::Infinite);
+    let mut chunks = voxel_garden::Chunks::new();
+    // Plants are placed at random, so look for a chunk that has some
+    let (chunk, data) = (0..20)
+        .map(|x| IVec2::new(x, 0))
+        .map(|chunk| (chunk, world.generate_chunk(chunk)))
+        .find(|(_, data)| !data.objects.is_empty())
+        .expect("no objects generated");
+    let id = data.objects[0].id;
+    chunks.insert(chunk, data);
+
+    let removed = chunks
+        .remove_object(ObjectId { chunk, id })
+        .expect("object not found");
+    assert_eq!(removed.id, id);
+    assert!(chunks
+        .get(&chunk)
+        .unwrap()
+        .objects
+        .iter()
+        .all(|o| o.id != id));
+
+    chunks.insert(chunk, world.generate_chunk(chunk));
+    assert!(chunks
+        .get(&chunk)
+        .unwrap()
+        .objects
+        .iter()
+        .all(|o| o.id != id));
+
+    // Putting it back makes it part of the chunk again
+    chunks.add_object(chunk, removed);
+    assert!(chunks
+        .get(&chunk)
+        .unwrap()
+        .objects
+        .iter()
+        .any(|o| o.id == id));
+    let planted = chunks.new_object(Vec::new());
+    assert_ne!(planted.id, id);
+}
+
+#[test]
+fn proxies_continue_into_neighbouring_chunks() {
+    let world = world(WorldBounds::Infinite);
+    let mut chunks = voxel_garden::Chunks::new();
+    for chunk in [IVec2::new(0, 0), IVec2::new(1, 0)] {
+        chunks.insert(chunk, world.generate_chunk(chunk));
+    }
+    // A block of 6x6x6 voxels high above the ground, from x = 29 to 34 across the border
+    let points = (0..216)
+        .map(|i| InstanceData {
+            position: Vec3::new((i % 6) as f32, (i / 36) as f32, (i / 6 % 6) as f32),
+            color: Vec4::new(0.4, 0.25, 0.1, 1.0),
+            is_water: 0,
+        })
+        .collect();
+    let model = Model {
+        points,
+        rotation: Quat::IDENTITY,
+        translation: Vec3::new(CHUNK_SIZE as f32 - 3.0, 100.0, 5.0),
+    };
+    let object = chunks.new_object(vec![model]);
+    assert!(object.has_proxy());
+    chunks.add_object(IVec2::ZERO, object);
+    chunks.remesh_dirty();
+
+    // Proxy blocks are 2 voxels wide and aligned to even coordinates, so the odd edges of the
+    // block grow by one voxel in both chunks
+    let edge = |vertices: &[voxel_garden::mesh::MeshVertex], max: bool| {
+        let xs = vertices
+            .iter()
+            .filter(|v| v.position.y > 90.0)
+            .map(|v| v.position.x);
+        if max {
+            xs.fold(f32::MIN, f32::max)
+        } else {
+            xs.fold(f32::MAX, f32::min)
+        }
+    };
+    let neighbour = IVec2::new(1, 0);
+    let full = &chunks.get(&neighbour).unwrap().mesh(0).vertices;
+    let proxy = &chunks
+        .mesh_source(neighbour, 0, true)
+        .unwrap()
+        .build()
+        .vertices;
+    assert_eq!(edge(full, true), 34.5);
+    assert_eq!(edge(proxy, true), 35.5);
+    let full = &chunks.get(&IVec2::ZERO).unwrap().mesh(0).vertices;
+    let proxy = &chunks
+        .mesh_source(IVec2::ZERO, 0, true)
+        .unwrap()
+        .build()
+        .vertices;
+    assert_eq!(edge(full, false), 28.5);
+    assert_eq!(edge(proxy, false), 27.5);
+}
+
+#[test]
+fn meshes_built_from_an_old_copy_are_dropped() {
+    let world = world(WorldBounds::Infinite);
+    let mut chunks = voxel_garden::Chunks::new();
+    chunks.insert(IVec2::ZERO, world.generate_chunk(IVec2::ZERO));
+
+    let source = chunks.mesh_source(IVec2::ZERO, 1, false).unwrap();
+    chunks.remove_voxel(IVec3::new(3, 30, 3));
+    chunks.remesh_dirty();
+    chunks.insert_mesh(&source, source.build());
+    assert!(chunks
+        .get(&IVec2::ZERO)
+        .unwrap()
+        .built_mesh(1, false)
+        .is_none());
+
+    let source = chunks.mesh_source(IVec2::ZERO, 1, false).unwrap();
+    chunks.insert_mesh(&source, source.build());
+    assert!(chunks
+        .get(&IVec2::ZERO)
+        .unwrap()
+        .built_mesh(1, false)
+        .is_some());
+    assert!(chunks.mesh_source(IVec2::ZERO, 1, false).is_none());
+}
+
+#[test]
+fn water_surface_is_the_top_of_the_highest_water_voxel() {
+    let world = world(WorldBounds::Infinite);
+    let mut chunks = voxel_garden::Chunks::new();
+    chunks.insert(IVec2::ZERO, world.generate_chunk(IVec2::ZERO));
+
+    let water = voxel_garden::voxels::Material::water(Vec4::ONE);
+    chunks.set_voxel(IVec3::new(3, 50, 4), water);
+    chunks.set_voxel(IVec3::new(3, 52, 4), water);
+    assert_eq!(chunks.water_surface(3, 4), Some(52.5));
+    assert_eq!(chunks.water_surface(3, CHUNK_SIZE + 4), None);
+}